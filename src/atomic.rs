@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use roxygen::roxygen;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+
+#[roxygen]
+/// Replace the contents of a file in one go, so it's never left half-written.
+/// The contents are written to a temporary file next to it, which is renamed over the original.
+/// A symlinked file is updated where the link points to, and keeps its permissions & owner.
+/// New files are only readable by their owner, as kubeconfigs hold credentials
+pub fn write(
+    /// The file to write
+    location: &Path,
+    /// The new contents of the file
+    contents: &[u8],
+) -> Result<()> {
+    let target = resolve_symlinks(location)?;
+    let directory = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut file = NamedTempFile::new_in(directory).with_context(|| {
+        format!(
+            "Could not create a temporary file in {}",
+            directory.display()
+        )
+    })?;
+    file.write_all(contents)
+        .with_context(|| format!("Could not write to {}", file.path().display()))?;
+
+    #[cfg(unix)]
+    copy_permissions(&target, file.as_file())?;
+
+    file.as_file()
+        .sync_all()
+        .with_context(|| format!("Could not write to {}", file.path().display()))?;
+    file.persist(&target)
+        .with_context(|| format!("Could not replace {}", target.display()))?;
+
+    // make sure the rename itself survives a crash
+    #[cfg(unix)]
+    if let Ok(directory) = std::fs::File::open(directory) {
+        let _ = directory.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Follow a (chain of) symlinks to the file that should actually be written
+fn resolve_symlinks(location: &Path) -> Result<PathBuf> {
+    let mut target = location.to_path_buf();
+    // bounded, in case of a symlink loop
+    for _ in 0..40 {
+        match std::fs::read_link(&target) {
+            Ok(link) => {
+                target = match target.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                }
+            }
+            Err(_) => return Ok(target),
+        }
+    }
+
+    anyhow::bail!(
+        "Too many levels of symbolic links at {}",
+        location.display()
+    )
+}
+
+#[cfg(unix)]
+/// Give the temporary file the mode & owner of the file it replaces, or 0600 for a new file
+fn copy_permissions(target: &Path, file: &std::fs::File) -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    match std::fs::metadata(target) {
+        Ok(metadata) => {
+            file.set_permissions(std::fs::Permissions::from_mode(metadata.mode() & 0o7777))
+                .with_context(|| {
+                    format!("Could not copy the permissions of {}", target.display())
+                })?;
+            // only root can give a file away, for everyone else this is a no-op or not permitted
+            let _ = std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()));
+        }
+        Err(_) => file
+            .set_permissions(std::fs::Permissions::from_mode(0o600))
+            .context("Could not restrict the permissions of the new file")?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use roxygen::roxygen;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{atomic, state::State, token};
+
+/// A copy of the kubeconfig files a command was about to change, taken before it wrote them
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Backup {
+    /// Identifies the backup, the name of its file
+    #[serde(skip)]
+    pub id: String,
+    /// When the backup was taken (unix timestamp)
+    pub created_at: u64,
+    /// The kman command that changed the files
+    pub command: String,
+    /// The files as they were before the command changed them
+    pub files: Vec<BackupFile>,
+}
+
+/// A single backed up kubeconfig file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// The location of the file
+    pub path: PathBuf,
+    /// The exact contents of the file, `None` when the file didn't exist yet
+    pub contents: Option<String>,
+}
+
+impl Backup {
+    /// The directory backups are kept in
+    fn dir() -> Result<PathBuf> {
+        Ok(State::data_dir()?.join("backups"))
+    }
+
+    #[roxygen]
+    /// Store a new backup, and remove the oldest ones beyond the retention.
+    /// Returns the new backup's id, unless backups are disabled
+    pub fn save(
+        /// The kman command that is changing the files
+        command: &str,
+        /// The files as they are before the command changes them
+        files: Vec<BackupFile>,
+        /// How many backups to keep, none are taken when 0
+        retention: usize,
+    ) -> Result<Option<String>> {
+        if retention == 0 {
+            return Ok(None);
+        }
+
+        let dir = Self::dir()?;
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+
+        let created_at = token::now();
+        let mut id = created_at;
+        while dir.join(format!("{id}.yaml")).exists() {
+            id += 1;
+        }
+
+        let backup = Backup {
+            id: id.to_string(),
+            created_at,
+            command: command.to_string(),
+            files,
+        };
+        let location = dir.join(format!("{id}.yaml"));
+        let yaml = serde_yml::to_string(&backup).context("Could not serialize backup")?;
+        atomic::write(&location, yaml.as_bytes())?;
+
+        for old in Self::list()?.into_iter().skip(retention) {
+            old.remove()?;
+        }
+
+        Ok(Some(id.to_string()))
+    }
+
+    /// All backups, newest first. Backups that can't be read are skipped
+    pub fn list() -> Result<Vec<Backup>> {
+        let dir = Self::dir()?;
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids: Vec<u64> = std::fs::read_dir(&dir)
+            .with_context(|| format!("Could not read {}", dir.display()))?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_suffix(".yaml")?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| {
+                Self::load(&id.to_string())
+                    .inspect_err(|e| warn!("Skipping backup {id}: {e:#}"))
+                    .ok()
+            })
+            .collect())
+    }
+
+    #[roxygen]
+    /// Load a single backup
+    pub fn load(
+        /// The backup's id
+        id: &str,
+    ) -> Result<Backup> {
+        let location = Self::dir()?.join(format!("{id}.yaml"));
+        if !location.exists() {
+            bail!("There is no backup with id {id}, see `kman backups list`");
+        }
+
+        let backup_str = std::fs::read_to_string(&location)
+            .with_context(|| format!("Could not read {}", location.display()))?;
+        let mut backup: Backup = serde_yml::from_str(&backup_str)
+            .with_context(|| format!("{} is not a valid kman backup", location.display()))?;
+        backup.id = id.to_string();
+
+        Ok(backup)
+    }
+
+    /// Delete this backup, unless it's gone already (e.g. removed by the retention)
+    pub fn remove(&self) -> Result<()> {
+        let location = Self::dir()?.join(format!("{}.yaml", self.id));
+        match std::fs::remove_file(&location) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_context(|| format!("Could not remove {}", location.display())),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use crate::kubeconfig::Cluster;
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use roxygen::roxygen;
+use std::time::Duration;
+use ureq::{
+    tls::{parse_pem, PemItem, RootCerts, TlsConfig},
+    Agent, Proxy,
+};
+
+/// How long a single request to a cluster may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long checking a token may take, kept short as it happens on every `list` and `select`
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[roxygen]
+/// Build an HTTP agent that talks to the given cluster the way kubectl would:
+/// trusting its `certificate-authority(-data)`, honouring `insecure-skip-tls-verify`
+/// and going through its `proxy-url` (or the proxy environment variables when unset).
+/// `tls-server-name` is not supported: the certificate is always verified against the server URL's hostname
+/// (ureq can't set a different name to verify), so kman warns that the connection may fail
+pub fn cluster_agent(
+    /// The cluster to connect to
+    cluster: &Cluster,
+) -> Result<Agent> {
+    let mut tls_config = TlsConfig::builder();
+
+    if cluster.insecure_skip_tls_verify == Some(true) {
+        tls_config = tls_config.disable_verification(true);
+    } else if let Some(pem) = cluster.certificate_authority_pem()? {
+        let certificates = parse_pem(pem.as_bytes())
+            .filter_map(|item| match item {
+                Ok(PemItem::Certificate(certificate)) => Some(Ok(certificate)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Certificate authority contains invalid PEM data")?;
+        tls_config = tls_config.root_certs(RootCerts::new_with_certs(&certificates));
+    }
+
+    if let Some(tls_server_name) = &cluster.tls_server_name {
+        warn!(
+            "kman ignores `tls-server-name` ({tls_server_name}) and verifies the certificate against {} instead",
+            cluster.server
+        );
+    }
+
+    let mut config = Agent::config_builder()
+        .tls_config(tls_config.build())
+        .http_status_as_error(false)
+        .max_redirects(0)
+        .timeout_global(Some(REQUEST_TIMEOUT));
+
+    if let Some(proxy_url) = cluster.proxy_url.as_deref().filter(|p| !p.is_empty()) {
+        let proxy =
+            Proxy::new(proxy_url).with_context(|| format!("Invalid proxy-url {proxy_url}"))?;
+        config = config.proxy(Some(proxy));
+    }
+
+    Ok(config.build().into())
+}
+
+/// Whether a cluster still accepts a context's token
+#[derive(Debug)]
+pub enum TokenStatus {
+    /// The token is valid, for the given user (if the cluster told us)
+    Valid(Option<String>),
+    /// The cluster rejected the token
+    Expired,
+    /// The cluster could not be reached, or gave an unexpected answer
+    Unreachable(String),
+}
+
+#[roxygen]
+/// Check whether a bearer token is still accepted by the cluster.
+/// OpenShift clusters are asked who the token belongs to, vanilla Kubernetes clusters get a `SelfSubjectReview`
+/// and clusters that support neither fall back to reading `/api`, which any authenticated user may do
+pub fn check_token(
+    /// The cluster to check against
+    cluster: &Cluster,
+    /// The bearer token to check
+    token: &str,
+) -> TokenStatus {
+    let agent = match cluster_agent(cluster) {
+        Ok(agent) => agent,
+        Err(e) => return TokenStatus::Unreachable(format!("{e:#}")),
+    };
+    let server = cluster.server.trim_end_matches('/');
+    let authorization = format!("Bearer {token}");
+
+    let checks = [
+        (
+            format!("{server}/apis/user.openshift.io/v1/users/~"),
+            None,
+            "/metadata/name",
+        ),
+        (
+            format!("{server}/apis/authentication.k8s.io/v1/selfsubjectreviews"),
+            Some(serde_json::json!({
+                "apiVersion": "authentication.k8s.io/v1",
+                "kind": "SelfSubjectReview",
+            })),
+            "/status/userInfo/username",
+        ),
+        (format!("{server}/api"), None, ""),
+    ];
+
+    let mut authenticated = false;
+    for (url, body, username_pointer) in checks {
+        let response = match &body {
+            Some(body) => agent
+                .post(&url)
+                .header("Authorization", &authorization)
+                .config()
+                .timeout_global(Some(CHECK_TIMEOUT))
+                .build()
+                .send_json(body),
+            None => agent
+                .get(&url)
+                .header("Authorization", &authorization)
+                .config()
+                .timeout_global(Some(CHECK_TIMEOUT))
+                .build()
+                .call(),
+        };
+
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => return TokenStatus::Unreachable(e.to_string()),
+        };
+
+        match response.status().as_u16() {
+            401 => return TokenStatus::Expired,
+            // this API doesn't exist on this cluster, try the next one
+            404 => debug!("{url} does not exist"),
+            // authenticated, just not allowed to ask. try the next one to find out who we are
+            403 => authenticated = true,
+            status if response.status().is_success() => {
+                debug!("{url} returned {status}, token is valid");
+                let username = response
+                    .body_mut()
+                    .read_json::<serde_json::Value>()
+                    .ok()
+                    .and_then(|v| v.pointer(username_pointer)?.as_str().map(String::from));
+                return TokenStatus::Valid(username);
+            }
+            status => return TokenStatus::Unreachable(format!("{url} returned {status}")),
+        }
+    }
+
+    if authenticated {
+        TokenStatus::Valid(None)
+    } else {
+        TokenStatus::Unreachable("cluster does not look like a Kubernetes API server".to_string())
+    }
+}
+
+#[roxygen]
+/// List the namespaces a token may use on a cluster, sorted by name.
+/// OpenShift clusters are asked for the user's projects, which unlike namespaces every user may list
+pub fn list_namespaces(
+    /// The cluster to ask
+    cluster: &Cluster,
+    /// The bearer token to authenticate with
+    token: &str,
+) -> Result<Vec<String>> {
+    let agent = cluster_agent(cluster)?;
+    let server = cluster.server.trim_end_matches('/');
+    let authorization = format!("Bearer {token}");
+
+    for url in [
+        format!("{server}/apis/project.openshift.io/v1/projects"),
+        format!("{server}/api/v1/namespaces"),
+    ] {
+        let mut response = agent
+            .get(&url)
+            .header("Authorization", &authorization)
+            .config()
+            .timeout_global(Some(CHECK_TIMEOUT))
+            .build()
+            .call()
+            .with_context(|| format!("Could not reach {server}"))?;
+
+        match response.status().as_u16() {
+            401 => bail!("The cluster rejected the token, run `kman refresh` to get a new one"),
+            403 => bail!("The token is not allowed to list namespaces"),
+            // this API doesn't exist on this cluster, try the next one
+            404 => debug!("{url} does not exist"),
+            status if response.status().is_success() => {
+                debug!("{url} returned {status}");
+                let list: serde_json::Value = response
+                    .body_mut()
+                    .read_json()
+                    .with_context(|| format!("{url} returned an invalid namespace list"))?;
+                let mut namespaces: Vec<String> = list["items"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|item| item.pointer("/metadata/name")?.as_str().map(String::from))
+                    .collect();
+                namespaces.sort();
+                return Ok(namespaces);
+            }
+            status => bail!("{url} returned {status}"),
+        }
+    }
+
+    bail!("The cluster does not look like a Kubernetes API server")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{self, MockServer};
+    use base64::prelude::*;
+
+    const USERS_URL: &str = "GET /apis/user.openshift.io/v1/users/~";
+    const REVIEW_URL: &str = "POST /apis/authentication.k8s.io/v1/selfsubjectreviews";
+    const API_URL: &str = "GET /api";
+
+    /// Check a token against a mock cluster that answers each API with the status for its URL,
+    /// returning the result along with the requests the cluster received
+    fn check(statuses: &'static [(&'static str, u16)]) -> (TokenStatus, Vec<String>) {
+        let server = MockServer::start(move |request| {
+            if mock_server::header(request, "Authorization").as_deref() != Some("Bearer token") {
+                return mock_server::status(401);
+            }
+            let url = format!("{} {}", request.method(), request.url());
+            let status = statuses
+                .iter()
+                .find(|(u, _)| *u == url)
+                .map(|(_, status)| *status)
+                .unwrap_or(404);
+            let body = match url.as_str() {
+                USERS_URL => serde_json::json!({"metadata": {"name": "alice"}}),
+                REVIEW_URL => serde_json::json!({"status": {"userInfo": {"username": "bob"}}}),
+                _ => serde_json::json!({"kind": "APIVersions"}),
+            };
+            mock_server::json(status, body)
+        });
+        let cluster = Cluster {
+            server: server.url.clone(),
+            ..Default::default()
+        };
+
+        (check_token(&cluster, "token"), server.requests())
+    }
+
+    #[test]
+    fn asks_openshift_who_the_token_belongs_to() {
+        let (status, requests) = check(&[(USERS_URL, 200)]);
+        assert!(matches!(status, TokenStatus::Valid(Some(user)) if user == "alice"));
+        assert_eq!(requests, [USERS_URL]);
+    }
+
+    #[test]
+    fn falls_back_to_a_self_subject_review() {
+        let (status, requests) = check(&[(REVIEW_URL, 201)]);
+        assert!(matches!(status, TokenStatus::Valid(Some(user)) if user == "bob"));
+        assert_eq!(requests, [USERS_URL, REVIEW_URL]);
+    }
+
+    #[test]
+    fn falls_back_to_reading_the_api() {
+        let (status, requests) = check(&[(API_URL, 200)]);
+        assert!(matches!(status, TokenStatus::Valid(None)));
+        assert_eq!(requests, [USERS_URL, REVIEW_URL, API_URL]);
+    }
+
+    #[test]
+    fn unauthorized_means_expired() {
+        let (status, requests) = check(&[(USERS_URL, 401)]);
+        assert!(matches!(status, TokenStatus::Expired));
+        assert_eq!(requests, [USERS_URL]);
+
+        let (status, _) = check(&[(REVIEW_URL, 401)]);
+        assert!(matches!(status, TokenStatus::Expired));
+    }
+
+    #[test]
+    fn forbidden_means_authenticated() {
+        let (status, requests) = check(&[(USERS_URL, 403), (REVIEW_URL, 403), (API_URL, 403)]);
+        assert!(matches!(status, TokenStatus::Valid(None)));
+        assert_eq!(requests, [USERS_URL, REVIEW_URL, API_URL]);
+
+        // the next API can still tell who the token belongs to
+        let (status, _) = check(&[(USERS_URL, 403), (REVIEW_URL, 201)]);
+        assert!(matches!(status, TokenStatus::Valid(Some(user)) if user == "bob"));
+    }
+
+    #[test]
+    fn not_found_everywhere_is_not_a_cluster() {
+        let (status, requests) = check(&[]);
+        assert!(matches!(status, TokenStatus::Unreachable(_)));
+        assert_eq!(requests, [USERS_URL, REVIEW_URL, API_URL]);
+    }
+
+    #[test]
+    fn unexpected_answers_are_unreachable() {
+        let (status, requests) = check(&[(USERS_URL, 500)]);
+        assert!(matches!(status, TokenStatus::Unreachable(reason) if reason.contains("500")));
+        assert_eq!(requests, [USERS_URL]);
+    }
+
+    #[test]
+    fn unreachable_clusters_are_unreachable() {
+        // nothing listens on the discard port
+        let cluster = Cluster {
+            server: "http://127.0.0.1:9".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            check_token(&cluster, "token"),
+            TokenStatus::Unreachable(_)
+        ));
+    }
+
+    /// A cluster behind TLS that accepts any token and tells it belongs to alice
+    fn tls_cluster() -> (Cluster, MockServer) {
+        let server = MockServer::start_tls(|_| {
+            mock_server::json(200, serde_json::json!({"metadata": {"name": "alice"}}))
+        });
+        let cluster = Cluster {
+            server: server.url.clone(),
+            ..Default::default()
+        };
+        (cluster, server)
+    }
+
+    #[test]
+    fn trusts_the_certificate_authority_data() {
+        let (mut cluster, server) = tls_cluster();
+        cluster.certificate_authority_data = Some(BASE64_STANDARD.encode(mock_server::TEST_CA));
+
+        let status = check_token(&cluster, "token");
+        assert!(matches!(status, TokenStatus::Valid(Some(user)) if user == "alice"));
+        assert_eq!(server.requests(), [USERS_URL]);
+    }
+
+    #[test]
+    fn rejects_untrusted_certificates() {
+        let (cluster, server) = tls_cluster();
+
+        let status = check_token(&cluster, "token");
+        assert!(matches!(status, TokenStatus::Unreachable(_)));
+        assert_eq!(server.requests(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn skips_verification_when_insecure() {
+        let (mut cluster, _server) = tls_cluster();
+        cluster.insecure_skip_tls_verify = Some(true);
+
+        let status = check_token(&cluster, "token");
+        assert!(matches!(status, TokenStatus::Valid(Some(user)) if user == "alice"));
+    }
+
+    #[test]
+    fn connects_through_the_proxy_url() {
+        let proxy = MockServer::start(|_| mock_server::status(200));
+        let cluster = Cluster {
+            server: "http://cluster.invalid".to_string(),
+            proxy_url: Some(proxy.url.clone()),
+            ..Default::default()
+        };
+
+        check_token(&cluster, "token");
+        assert_eq!(
+            proxy.requests().first().map(String::as_str),
+            Some("CONNECT cluster.invalid:80")
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use roxygen::roxygen;
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::token::TokenFormat;
+
+/// How many backups kman keeps when the config doesn't say
+const DEFAULT_BACKUPS: usize = 10;
+
+/// kman's configuration, read from `~/.config/kman/config.yaml` (or `$KMAN_CONFIG`)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The format of pasted tokens, for clusters that don't configure their own
+    #[serde(default)]
+    pub token_format: TokenFormat,
+    /// Settings per cluster, keyed by the cluster's name in the kubeconfig
+    #[serde(default)]
+    pub clusters: HashMap<String, ClusterConfig>,
+    /// How many backups of the kubeconfig to keep, 0 disables them
+    #[serde(default = "default_backups")]
+    pub backups: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token_format: TokenFormat::default(),
+            clusters: HashMap::new(),
+            backups: DEFAULT_BACKUPS,
+        }
+    }
+}
+
+fn default_backups() -> usize {
+    DEFAULT_BACKUPS
+}
+
+/// Settings for a single cluster
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ClusterConfig {
+    /// The format of pasted tokens for this cluster
+    pub token_format: Option<TokenFormat>,
+}
+
+impl Config {
+    /// The location of the config file
+    fn location() -> Result<PathBuf> {
+        if let Some(location) = std::env::var_os("KMAN_CONFIG") {
+            return Ok(location.into());
+        }
+
+        Ok(ProjectDirs::from("", "", "kman")
+            .context("Could not determine your home directory")?
+            .config_dir()
+            .join("config.yaml"))
+    }
+
+    /// Load the config file, falling back to the defaults when there is none
+    pub fn load() -> Result<Config> {
+        let location = Self::location()?;
+        if !location.exists() {
+            return Ok(Config::default());
+        }
+
+        let config_str = std::fs::read_to_string(&location)
+            .with_context(|| format!("Could not read {}", location.display()))?;
+        if config_str.trim().is_empty() {
+            return Ok(Config::default());
+        }
+
+        serde_yml::from_str(&config_str)
+            .with_context(|| format!("{} is not a valid kman config file", location.display()))
+    }
+
+    #[roxygen]
+    /// The token format to expect for a cluster
+    pub fn token_format(
+        &self,
+        /// The name of the cluster
+        cluster_name: &str,
+    ) -> &TokenFormat {
+        self.clusters
+            .get(cluster_name)
+            .and_then(|c| c.token_format.as_ref())
+            .unwrap_or(&self.token_format)
+    }
+}
@@ -0,0 +1,287 @@
+use colored::Colorize;
+use regex::{Captures, Regex};
+use roxygen::roxygen;
+use serde_yml::Value;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::sync::OnceLock;
+
+#[roxygen]
+/// A colored unified diff between two versions of a file, empty when they're the same
+pub fn unified(
+    /// The old contents
+    old: &str,
+    /// The new contents
+    new: &str,
+    /// The name of the old version in the diff's header
+    old_label: &str,
+    /// The name of the new version in the diff's header
+    new_label: &str,
+) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(old_label, new_label)
+        .to_string()
+        .lines()
+        .map(|line| {
+            if line.starts_with("+++") || line.starts_with("---") {
+                line.bold().to_string()
+            } else if line.starts_with('+') {
+                line.green().to_string()
+            } else if line.starts_with('-') {
+                line.red().to_string()
+            } else if line.starts_with("@@") {
+                line.cyan().to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Keys in a kubeconfig whose values are secret
+const SECRET_KEYS: &[&str] = &[
+    "token",
+    "password",
+    "client-key-data",
+    "client-secret",
+    "access-token",
+    "id-token",
+    "refresh-token",
+];
+
+#[roxygen]
+/// Replace the secrets in a kubeconfig with a short hash, so a diff shows which secrets changed without showing them.
+/// Secrets on a single line, in block scalars (`token: |`) and in flow mappings (`{token: ...}`) are redacted in place.
+/// Should a secret still show up in any other form, the whole document is redacted & serialized instead
+pub fn redact_secrets(
+    /// The kubeconfig's YAML
+    yaml: &str,
+) -> String {
+    let text = redact_text(yaml);
+
+    let Ok(mut document) = serde_yml::from_str::<Value>(yaml) else {
+        return text;
+    };
+    let mut secrets = Vec::new();
+    collect_secrets(&document, &mut secrets);
+    let leaked = secrets
+        .iter()
+        .flat_map(|secret| secret.split_whitespace())
+        .any(|part| part.len() >= MIN_LEAK_LENGTH && text.contains(part));
+    if !leaked {
+        return text;
+    }
+
+    redact_value(&mut document);
+    serde_yml::to_string(&document).unwrap_or_default()
+}
+
+/// Parts of secrets shorter than this are too likely to show up elsewhere in a kubeconfig to count as leaked
+const MIN_LEAK_LENGTH: usize = 4;
+
+/// Redact the secrets in a kubeconfig's text, line by line
+fn redact_text(yaml: &str) -> String {
+    let keys = SECRET_KEYS.join("|");
+    let line_secret = Regex::new(&format!(r"^(\s*(?:-\s+)?(?:{keys}):[ \t]*)(\S.*?)[ \t]*$"))
+        .expect("secret keys form a valid regex");
+    let block_indicator = Regex::new(r"^[|>][-+0-9]*(?:[ \t]+#.*)?$").expect("valid regex");
+    let flow_secret = Regex::new(&format!(
+        r#"([{{,][ \t]*(?:{keys})[ \t]*:[ \t]*)("(?:[^"\\]|\\.)*"|'(?:[^']|'')*'|[^,}}\s](?:[^,}}]*[^,}}\s])?)"#
+    ))
+    .expect("secret keys form a valid regex");
+
+    let mut out = String::new();
+    let mut lines = yaml.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let line_break = &line[content.len()..];
+
+        if let Some(captures) = line_secret.captures(content) {
+            let value = &captures[2];
+            if block_indicator.is_match(value) {
+                // the block scalar holds every following line indented further than its key
+                let indent = content.len() - content.trim_start().len();
+                let mut block = String::new();
+                let mut blank_lines = String::new();
+                while let Some(next) = lines.peek() {
+                    if next.trim().is_empty() {
+                        blank_lines.push_str(next);
+                    } else if next.len() - next.trim_start().len() > indent {
+                        block.push_str(&blank_lines);
+                        block.push_str(next.trim());
+                        block.push('\n');
+                        blank_lines.clear();
+                    } else {
+                        break;
+                    }
+                    lines.next();
+                }
+                out.push_str(&format!("{}{}{line_break}", &captures[1], redacted(&block)));
+                out.push_str(&blank_lines);
+                continue;
+            }
+            if !value.starts_with(['{', '[']) {
+                let value = value.trim_matches(['\'', '"']);
+                out.push_str(&format!("{}{}{line_break}", &captures[1], redacted(value)));
+                continue;
+            }
+        }
+
+        out.push_str(&flow_secret.replace_all(line, |captures: &Captures| {
+            let value = captures[2].trim_matches(['\'', '"']);
+            format!("{}{}", &captures[1], redacted(value))
+        }));
+    }
+
+    out
+}
+
+/// Collect the values of all secret keys in a document
+fn collect_secrets(value: &Value, secrets: &mut Vec<String>) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                match (key.as_str(), value) {
+                    (Some(key), Value::String(secret)) if SECRET_KEYS.contains(&key) => {
+                        secrets.push(secret.clone())
+                    }
+                    _ => collect_secrets(value, secrets),
+                }
+            }
+        }
+        Value::Sequence(items) => items.iter().for_each(|item| collect_secrets(item, secrets)),
+        Value::Tagged(tagged) => collect_secrets(&tagged.value, secrets),
+        _ => {}
+    }
+}
+
+/// Redact the values of all secret keys in a document
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                match (key.as_str(), &*value) {
+                    (Some(key), Value::String(secret)) if SECRET_KEYS.contains(&key) => {
+                        *value = Value::String(redacted(secret));
+                    }
+                    _ => redact_value(value),
+                }
+            }
+        }
+        Value::Sequence(items) => items.iter_mut().for_each(redact_value),
+        Value::Tagged(tagged) => redact_value(&mut tagged.value),
+        _ => {}
+    }
+}
+
+/// What a secret is replaced with: a short hash, enough to tell whether it changed within a diff.
+/// The hash is keyed with a random key for every run, so it can't be used to brute-force the secret
+fn redacted(secret: &str) -> String {
+    static KEY: OnceLock<Option<[u8; 64]>> = OnceLock::new();
+    let key = KEY.get_or_init(|| {
+        let mut key = [0; 64];
+        getrandom::fill(&mut key).ok().map(|_| key)
+    });
+    let Some(key) = key else {
+        return "<redacted>".to_string();
+    };
+
+    // HMAC-SHA256, with a key of exactly one block
+    let pad = |byte: u8| key.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(secret.as_bytes())
+        .finalize();
+    let hash = Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize();
+
+    let hash: String = hash.iter().take(4).map(|b| format!("{b:02x}")).collect();
+    format!("<redacted {hash}>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_single_line_secrets() {
+        let out = redact_secrets(
+            "users:\n- name: a\n  user:\n    token: 'sha256~secret-token'\n    username: admin\n- password: hunter2-password\n",
+        );
+        assert_eq!(
+            out,
+            format!(
+                "users:\n- name: a\n  user:\n    token: {}\n    username: admin\n- password: {}\n",
+                redacted("sha256~secret-token"),
+                redacted("hunter2-password")
+            )
+        );
+    }
+
+    #[test]
+    fn redacts_block_scalars() {
+        let yaml = "users:\n- name: a\n  user:\n    client-key-data: >-\n      first-secret-line\n\n      second-secret-line\n\n    token: |\n      secret-token\n  other: value\n";
+        let out = redact_secrets(yaml);
+        assert!(!out.contains("secret-line"));
+        assert!(!out.contains("secret-token"));
+        assert!(out.contains("    client-key-data: <redacted "));
+        assert!(out.contains("\n\n    token: <redacted "));
+        assert!(out.ends_with(">\n  other: value\n"));
+    }
+
+    #[test]
+    fn redacts_flow_mappings() {
+        let out = redact_secrets(
+            "users:\n- name: a\n  user: {token: secret-token, username: admin}\n- name: b\n  user: {password: \"secret, with comma\"}\n",
+        );
+        assert_eq!(
+            out,
+            format!(
+                "users:\n- name: a\n  user: {{token: {}, username: admin}}\n- name: b\n  user: {{password: {}}}\n",
+                redacted("secret-token"),
+                redacted("secret, with comma")
+            )
+        );
+    }
+
+    #[test]
+    fn redacts_the_whole_document_when_secrets_remain() {
+        // a quoted scalar continued on the next line isn't recognized in the text
+        let out = redact_secrets(
+            "users:\n- name: a\n  user:\n    token: \"first-part\n      second-part\"\n",
+        );
+        assert!(!out.contains("first-part"));
+        assert!(!out.contains("second-part"));
+        assert!(out.contains("token: <redacted "));
+    }
+
+    #[test]
+    fn hashes_secrets_with_a_key() {
+        assert_eq!(redacted("hunter2"), redacted("hunter2"));
+        assert_ne!(redacted("hunter2"), redacted("hunter3"));
+
+        // not a plain SHA-256 of the secret, which could be brute-forced
+        let unkeyed: String = Sha256::digest("hunter2")
+            .iter()
+            .take(4)
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_ne!(redacted("hunter2"), format!("<redacted {unkeyed}>"));
+    }
+
+    #[test]
+    fn shows_changed_lines() {
+        assert_eq!(unified("a\n", "a\n", "old", "new"), "");
+        let diff = unified("a\nb\n", "a\nc\n", "old", "new");
+        assert!(diff.contains("-b"));
+        assert!(diff.contains("+c"));
+    }
+}
@@ -1,23 +1,318 @@
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use roxygen::roxygen;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yml::Mapping;
+use std::collections::{BTreeMap, HashMap};
 
 // Created manually (and adapted to fit OC) using: https://pkg.go.dev/k8s.io/client-go/tools/clientcmd/api/v1#Config
 /// KubeConfig holds the information needed to build connect to remote Kubernetes clusters as a given user
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct KubeConfig {
     /// The api version
-    #[serde(rename = "apiVersion")]
+    #[serde(rename = "apiVersion", default = "default_api_version")]
     pub api_version: String,
     /// The kind
+    #[serde(default = "default_kind")]
     pub kind: String,
     /// Clusters is a map of referable names to cluster configs
+    #[serde(default, deserialize_with = "nullable")]
     pub clusters: Vec<NamedCluster>,
     /// Contexts is a map of referable names to context configs
+    #[serde(default, deserialize_with = "nullable")]
     pub contexts: Vec<NamedContext>,
-    /// CurrentContext is the name of the context that you would like to use by default
-    #[serde(rename = "current-context")]
+    /// CurrentContext is the name of the context that you would like to use by default.
+    /// Left out when empty, so files that don't set one (e.g. the extra files in `KUBECONFIG`) don't get one
+    #[serde(
+        rename = "current-context",
+        default,
+        deserialize_with = "nullable",
+        skip_serializing_if = "String::is_empty"
+    )]
     pub current_context: String,
     /// Users is a map of referable users with their tokens
+    #[serde(default, deserialize_with = "nullable")]
     pub users: Vec<NamedUser>,
+    /// Extra holds all top-level fields kman does not model (e.g. `preferences`, `extensions`), so they are written back untouched
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+impl Default for KubeConfig {
+    fn default() -> Self {
+        Self {
+            api_version: default_api_version(),
+            kind: default_kind(),
+            clusters: Vec::new(),
+            contexts: Vec::new(),
+            current_context: String::new(),
+            users: Vec::new(),
+            extra: Mapping::new(),
+        }
+    }
+}
+
+fn default_api_version() -> String {
+    "v1".to_string()
+}
+
+fn default_kind() -> String {
+    "Config".to_string()
+}
+
+/// kubectl writes empty fields as `null`, treat those the same as a missing field
+fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl KubeConfig {
+    #[roxygen]
+    /// Merge multiple kubeconfigs the way kubectl does for a colon-separated `KUBECONFIG`:
+    /// the first file to define a cluster, user or context (or the current-context) wins
+    pub fn merge(
+        /// The kubeconfigs, in `KUBECONFIG` order
+        files: &[KubeConfig],
+    ) -> (KubeConfig, Origins) {
+        let mut merged = KubeConfig::default();
+        let mut origins = Origins::default();
+
+        if let Some(first) = files.first() {
+            merged.api_version = first.api_version.clone();
+            merged.kind = first.kind.clone();
+        }
+
+        for (file, kubeconfig) in files.iter().enumerate() {
+            merge_entries(
+                &mut merged.clusters,
+                &mut origins.clusters,
+                &kubeconfig.clusters,
+                file,
+            );
+            merge_entries(
+                &mut merged.users,
+                &mut origins.users,
+                &kubeconfig.users,
+                file,
+            );
+            merge_entries(
+                &mut merged.contexts,
+                &mut origins.contexts,
+                &kubeconfig.contexts,
+                file,
+            );
+
+            if merged.current_context.is_empty() && !kubeconfig.current_context.is_empty() {
+                merged.current_context = kubeconfig.current_context.clone();
+                origins.current_context = Some(file);
+            }
+
+            for (key, value) in &kubeconfig.extra {
+                if !merged.extra.contains_key(key) {
+                    merged.extra.insert(key.clone(), value.clone());
+                }
+            }
+        }
+
+        (merged, origins)
+    }
+
+    #[roxygen]
+    /// Split this (merged) kubeconfig back into the files it was merged from.
+    /// Every entry ends up in the file it originally came from, new entries are added to the first file
+    /// and entries kubectl ignored because an earlier file defined the same name are left untouched.
+    pub fn split(
+        &self,
+        /// The kubeconfigs as they were loaded, in `KUBECONFIG` order
+        files: &[KubeConfig],
+        /// Where each entry of this kubeconfig came from
+        origins: &Origins,
+    ) -> Vec<KubeConfig> {
+        let (_, loaded) = KubeConfig::merge(files);
+
+        files
+            .iter()
+            .enumerate()
+            .map(|(file, original)| {
+                let mut kubeconfig = original.clone();
+                kubeconfig.clusters = split_entries(
+                    &self.clusters,
+                    &original.clusters,
+                    file,
+                    &origins.clusters,
+                    &loaded.clusters,
+                );
+                kubeconfig.users = split_entries(
+                    &self.users,
+                    &original.users,
+                    file,
+                    &origins.users,
+                    &loaded.users,
+                );
+                kubeconfig.contexts = split_entries(
+                    &self.contexts,
+                    &original.contexts,
+                    file,
+                    &origins.contexts,
+                    &loaded.contexts,
+                );
+                if origins.current_context.unwrap_or(0) == file {
+                    kubeconfig.current_context = self.current_context.clone();
+                }
+                kubeconfig
+            })
+            .collect()
+    }
+
+    #[roxygen]
+    /// Re-apply the changes made to a file on top of the version that is on disk now,
+    /// for when another program changed the file after kman read it.
+    /// Entries changed by both keep kman's version
+    pub fn rebase(
+        &self,
+        /// The file as kman loaded it
+        base: &KubeConfig,
+        /// The file as it is on disk now
+        on_disk: &KubeConfig,
+    ) -> KubeConfig {
+        let mut rebased = on_disk.clone();
+        rebased.clusters = rebase_entries(&self.clusters, &base.clusters, &on_disk.clusters);
+        rebased.users = rebase_entries(&self.users, &base.users, &on_disk.users);
+        rebased.contexts = rebase_entries(&self.contexts, &base.contexts, &on_disk.contexts);
+        if self.current_context != base.current_context {
+            rebased.current_context = self.current_context.clone();
+        }
+
+        rebased
+    }
+}
+
+/// Where an entry of a merged kubeconfig was loaded from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Origin {
+    /// Index of the file in the `KUBECONFIG` list
+    pub file: usize,
+    /// Position of the entry within that file
+    pub index: usize,
+}
+
+/// Origins tracks which file every cluster, user and context of a merged kubeconfig belongs to, keyed by name
+#[derive(Debug, Default, Clone)]
+pub struct Origins {
+    /// Origins of the clusters
+    pub clusters: HashMap<String, Origin>,
+    /// Origins of the users
+    pub users: HashMap<String, Origin>,
+    /// Origins of the contexts
+    pub contexts: HashMap<String, Origin>,
+    /// The file that sets the current-context, if any
+    pub current_context: Option<usize>,
+}
+
+/// Named is implemented by the `name`d entries in a kubeconfig's `clusters`, `users` and `contexts`
+pub trait Named {
+    /// The name of the entry
+    fn name(&self) -> &str;
+}
+
+impl Named for NamedCluster {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Named for NamedUser {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Named for NamedContext {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Add the entries of a single file to the merged entries, skipping names that are already defined
+fn merge_entries<T: Named + Clone>(
+    merged: &mut Vec<T>,
+    origins: &mut HashMap<String, Origin>,
+    entries: &[T],
+    file: usize,
+) {
+    for (index, entry) in entries.iter().enumerate() {
+        if !origins.contains_key(entry.name()) {
+            origins.insert(entry.name().to_string(), Origin { file, index });
+            merged.push(entry.clone());
+        }
+    }
+}
+
+/// Apply the entries that were added, changed or removed compared to `base` to the entries on disk
+fn rebase_entries<T: Named + Clone + PartialEq>(
+    changed: &[T],
+    base: &[T],
+    on_disk: &[T],
+) -> Vec<T> {
+    let mut entries: Vec<T> = on_disk
+        .iter()
+        .filter(|entry| {
+            let removed = base.iter().any(|e| e.name() == entry.name())
+                && !changed.iter().any(|e| e.name() == entry.name());
+            !removed
+        })
+        .cloned()
+        .collect();
+
+    for entry in changed {
+        if base.contains(entry) {
+            continue;
+        }
+        match entries.iter_mut().find(|e| e.name() == entry.name()) {
+            Some(existing) => *existing = entry.clone(),
+            None => entries.push(entry.clone()),
+        }
+    }
+
+    entries
+}
+
+/// Rebuild the entries of a single file from the merged entries
+fn split_entries<T: Named + Clone>(
+    merged: &[T],
+    original: &[T],
+    file: usize,
+    origins: &HashMap<String, Origin>,
+    loaded: &HashMap<String, Origin>,
+) -> Vec<T> {
+    let mut entries = Vec::new();
+
+    for (index, entry) in original.iter().enumerate() {
+        let origin = Origin { file, index };
+        if let Some(current) = merged
+            .iter()
+            .find(|e| origins.get(e.name()) == Some(&origin))
+        {
+            entries.push(current.clone());
+        } else if loaded.get(entry.name()) != Some(&origin) {
+            // shadowed by an earlier file, kubectl ignores it but it's not ours to remove
+            entries.push(entry.clone());
+        }
+    }
+
+    if file == 0 {
+        entries.extend(
+            merged
+                .iter()
+                .filter(|e| !origins.contains_key(e.name()))
+                .cloned(),
+        );
+    }
+
+    entries
 }
 
 /// NamedUser relates nicknames to user information
@@ -27,13 +322,137 @@ pub struct NamedUser {
     pub name: String,
     /// User holds the user information
     pub user: User,
+    /// Extra holds any unmodelled fields next to the user's name
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
-/// User contains information on the authenticated user
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+/// User contains information that describes identity information. This is used to tell the kubernetes cluster who you are
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct User {
-    /// Token is the user's sha256 token
-    pub token: String,
+    /// ClientCertificate is the path to a client cert file for TLS.
+    #[serde(rename = "client-certificate", skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<String>,
+    /// ClientCertificateData contains PEM-encoded data from a client cert file for TLS. Overrides ClientCertificate
+    #[serde(
+        rename = "client-certificate-data",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub client_certificate_data: Option<String>,
+    /// ClientKey is the path to a client key file for TLS.
+    #[serde(rename = "client-key", skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    /// ClientKeyData contains PEM-encoded data from a client key file for TLS. Overrides ClientKey
+    #[serde(rename = "client-key-data", skip_serializing_if = "Option::is_none")]
+    pub client_key_data: Option<String>,
+    /// Token is the bearer token for authentication to the kubernetes cluster.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// TokenFile is a pointer to a file that contains a bearer token (as described above). If both Token and TokenFile are present, TokenFile takes precedence.
+    #[serde(rename = "tokenFile", skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<String>,
+    /// Impersonate is the username to impersonate. The name matches the flag.
+    #[serde(rename = "as", skip_serializing_if = "Option::is_none")]
+    pub impersonate: Option<String>,
+    /// ImpersonateUID is the uid to impersonate.
+    #[serde(rename = "as-uid", skip_serializing_if = "Option::is_none")]
+    pub impersonate_uid: Option<String>,
+    /// ImpersonateGroups is the groups to impersonate.
+    #[serde(rename = "as-groups", skip_serializing_if = "Option::is_none")]
+    pub impersonate_groups: Option<Vec<String>>,
+    /// ImpersonateUserExtra contains additional information for impersonated user.
+    #[serde(rename = "as-user-extra", skip_serializing_if = "Option::is_none")]
+    pub impersonate_user_extra: Option<BTreeMap<String, Vec<String>>>,
+    /// Username is the username for basic authentication to the kubernetes cluster.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Password is the password for basic authentication to the kubernetes cluster.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// AuthProvider specifies a custom authentication plugin for the kubernetes cluster.
+    #[serde(rename = "auth-provider", skip_serializing_if = "Option::is_none")]
+    pub auth_provider: Option<AuthProviderConfig>,
+    /// Exec specifies a custom exec-based authentication plugin for the kubernetes cluster.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<ExecConfig>,
+    /// Extra holds all user fields kman does not model (e.g. `extensions`)
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+impl User {
+    /// Whether this user authenticates with a plain bearer token that kman can refresh.
+    /// Users relying on certificates, token files, basic auth or plugins are left alone.
+    pub fn uses_token(&self) -> bool {
+        self.token.is_some()
+            || (self.client_certificate.is_none()
+                && self.client_certificate_data.is_none()
+                && self.token_file.is_none()
+                && self.username.is_none()
+                && self.auth_provider.is_none()
+                && self.exec.is_none())
+    }
+}
+
+/// AuthProviderConfig holds the configuration for a specified auth provider.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct AuthProviderConfig {
+    /// Name is the name of the auth provider (e.g. `oidc`)
+    pub name: String,
+    /// Config holds the provider specific settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<BTreeMap<String, String>>,
+    /// Extra holds any unmodelled auth provider fields
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// ExecConfig specifies a command to provide client credentials. The command is exec'd and outputs structured stdout holding credentials.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ExecConfig {
+    /// Command to execute.
+    pub command: String,
+    /// Arguments to pass to the command when executing it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    /// Env defines additional environment variables to expose to the process. These are unioned with the host's environment, as well as variables client-go uses to pass argument to the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<ExecEnvVar>>,
+    /// Preferred input version of the ExecInfo. The returned ExecCredentials MUST use the same encoding version as the input.
+    #[serde(rename = "apiVersion", skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    /// This text is shown to the user when the executable doesn't seem to be present.
+    #[serde(rename = "installHint", skip_serializing_if = "Option::is_none")]
+    pub install_hint: Option<String>,
+    /// ProvideClusterInfo determines whether or not to provide cluster information, which could potentially contain very large CA data, to this exec plugin as a part of the KUBERNETES_EXEC_INFO environment variable.
+    #[serde(rename = "provideClusterInfo", skip_serializing_if = "Option::is_none")]
+    pub provide_cluster_info: Option<bool>,
+    /// InteractiveMode determines this plugin's relationship with standard input.
+    #[serde(rename = "interactiveMode", skip_serializing_if = "Option::is_none")]
+    pub interactive_mode: Option<ExecInteractiveMode>,
+    /// Extra holds any unmodelled exec fields
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// ExecEnvVar is used for setting environment variables when executing an exec-based credential plugin.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ExecEnvVar {
+    /// Name of the environment variable
+    pub name: String,
+    /// Value of the environment variable
+    pub value: String,
+}
+
+/// ExecInteractiveMode is a string that describes an exec plugin's relationship with standard input.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub enum ExecInteractiveMode {
+    /// The exec plugin never uses standard input
+    Never,
+    /// The exec plugin wants to use standard input if it is available
+    IfAvailable,
+    /// The exec plugin requires standard input to function
+    Always,
 }
 
 /// NamedCluster relates nicknames to cluster information
@@ -43,33 +462,92 @@ pub struct NamedCluster {
     pub name: String,
     /// Cluster holds the cluster information
     pub cluster: Cluster,
+    /// Extra holds any unmodelled fields next to the cluster's name
+    #[serde(flatten)]
+    pub extra: Mapping,
 }
 
 /// Cluster contains information about how to communicate with a Kubernetes cluster
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Cluster {
     /// Server is the address of the Kubernetes cluster (https://hostname:port).
     pub server: String,
     /// TLSServerName is used to check server certificate. If TLSServerName is empty, the hostname used to contact the server is used.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "tls-server-name", skip_serializing_if = "Option::is_none")]
     pub tls_server_name: Option<String>,
     /// InsecureSkipTLSVerify skips the validity check for the server's certificate. This will make your HTTPS connections insecure.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub insecure_skip_verify: Option<bool>,
+    #[serde(
+        rename = "insecure-skip-tls-verify",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub insecure_skip_tls_verify: Option<bool>,
     /// CertificateAuthority is the path to a cert file for the certificate authority.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "certificate-authority",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub certificate_authority: Option<String>,
-    /// CertificateAuthorityData contains PEM-encoded certificate authority certificates. Overrides CertificateAuthority
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub certificate_authority_data: Option<Vec<u8>>,
+    /// CertificateAuthorityData contains base64 encoded, PEM-encoded certificate authority certificates. Overrides CertificateAuthority
+    #[serde(
+        rename = "certificate-authority-data",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub certificate_authority_data: Option<String>,
     /// ProxyURL is the URL to the proxy to be used for all requests made by this client. URLs with "http", "https", and "socks5" schemes are supported. If this configuration is not provided or the empty string, the client attempts to construct a proxy configuration from http_proxy and https_proxy environment variables. If these environment variables are not set, the client does not attempt to proxy requests.
     ///
     /// socks5 proxying does not currently support spdy streaming endpoints (exec, attach, port forward).
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "proxy-url", skip_serializing_if = "Option::is_none")]
     pub proxy_url: Option<String>,
     /// DisableCompression allows client to opt-out of response compression for all requests to the server. This is useful to speed up requests (specifically lists) when client-server network bandwidth is ample, by saving time on compression (server-side) and decompression (client-side): https://github.com/Kubernetes/Kubernetes/issues/112296.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "disable-compression",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub disable_compression: Option<bool>,
+    /// Extra holds all cluster fields kman does not model (e.g. `extensions`)
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+impl Cluster {
+    /// Get the PEM-encoded certificate authority bundle for this cluster, if any.
+    /// `certificate-authority-data` takes precedence over the `certificate-authority` file
+    pub fn certificate_authority_pem(&self) -> Result<Option<String>> {
+        if let Some(data) = &self.certificate_authority_data {
+            let decoded = BASE64_STANDARD
+                .decode(data.trim())
+                .context("certificate-authority-data is not valid base64")?;
+            let pem = String::from_utf8(decoded)
+                .context("certificate-authority-data does not contain PEM data")?;
+            return Ok(Some(pem));
+        }
+
+        if let Some(path) = &self.certificate_authority {
+            let pem = std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read certificate authority file {path}"))?;
+            return Ok(Some(pem));
+        }
+
+        Ok(None)
+    }
+
+    /// Suggest a human-friendly name for this cluster based on its server's hostname,
+    /// e.g. `https://api.prod.example.com:6443` becomes `prod`
+    pub fn suggested_name(&self) -> String {
+        let host = self
+            .server
+            .split_once("://")
+            .map_or(self.server.as_str(), |(_, rest)| rest);
+        let host = host.split(['/', ':']).next().unwrap_or(host);
+
+        if host.parse::<std::net::Ipv4Addr>().is_ok() {
+            return host.replace('.', "-");
+        }
+
+        let labels: Vec<&str> = host.split('.').collect();
+        let labels = labels.strip_prefix(&["api"]).unwrap_or(&labels);
+        labels.first().unwrap_or(&host).to_string()
+    }
 }
 
 /// NamedContext relates nicknames to context information
@@ -79,10 +557,28 @@ pub struct NamedContext {
     pub name: String,
     /// Context holds the context information
     pub context: ClusterContext,
+    /// Extra holds any unmodelled fields next to the context's name
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+impl NamedContext {
+    /// Whether this context looks like one created by `oc login`, which names its contexts
+    /// `<namespace>/<cluster>/<user>` and its users `<user>/<cluster>`
+    pub fn is_generated_by_oc_login(&self) -> bool {
+        let mut parts = self.name.splitn(3, '/');
+        let (Some(_namespace), Some(cluster), Some(user)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return false;
+        };
+
+        self.context.cluster == cluster && self.context.user == format!("{user}/{cluster}")
+    }
 }
 
 /// Context is a tuple of references to a cluster (how do I communicate with a Kubernetes cluster), a user (how do I identify myself), and a namespace (what subset of resources do I want to work with)
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ClusterContext {
     /// Cluster is the name of the cluster for this context
     pub cluster: String,
@@ -91,4 +587,216 @@ pub struct ClusterContext {
     /// Namespace is the default namespace to use on unspecified requests
     #[serde(skip_serializing_if = "Option::is_none")]
     pub namespace: Option<String>,
+    /// Extra holds all context fields kman does not model (e.g. `extensions`)
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> KubeConfig {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    /// A kubeconfig with a context, user and cluster for every name
+    fn kubeconfig(names: &[&str], current_context: &str, server: &str) -> KubeConfig {
+        let mut yaml = format!("current-context: '{current_context}'\nclusters:\n");
+        for name in names {
+            yaml.push_str(&format!(
+                "- name: {name}\n  cluster:\n    server: {server}\n"
+            ));
+        }
+        yaml.push_str("contexts:\n");
+        for name in names {
+            yaml.push_str(&format!(
+                "- name: {name}\n  context:\n    cluster: {name}\n    user: {name}\n"
+            ));
+        }
+        yaml.push_str("users:\n");
+        for name in names {
+            yaml.push_str(&format!("- name: {name}\n  user:\n    token: {server}\n"));
+        }
+        parse(&yaml)
+    }
+
+    fn names<T: Named>(entries: &[T]) -> Vec<&str> {
+        entries.iter().map(|e| e.name()).collect()
+    }
+
+    #[test]
+    fn the_first_file_to_define_an_entry_wins() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["a", "b"], "b", "https://second"),
+        ];
+        let (merged, origins) = KubeConfig::merge(&files);
+
+        assert_eq!(names(&merged.clusters), ["a", "b"]);
+        assert_eq!(merged.clusters[0].cluster.server, "https://first");
+        assert_eq!(merged.users[0].user.token.as_deref(), Some("https://first"));
+        assert_eq!(origins.clusters["a"], Origin { file: 0, index: 0 });
+        assert_eq!(origins.clusters["b"], Origin { file: 1, index: 1 });
+        assert_eq!(merged.current_context, "b");
+        assert_eq!(origins.current_context, Some(1));
+    }
+
+    #[test]
+    fn unchanged_kubeconfigs_split_into_the_original_files() {
+        let files = [
+            kubeconfig(&["a"], "a", "https://first"),
+            kubeconfig(&["a", "b"], "b", "https://second"),
+        ];
+        let (merged, origins) = KubeConfig::merge(&files);
+
+        assert_eq!(merged.split(&files, &origins), files);
+    }
+
+    #[test]
+    fn shadowed_entries_are_left_in_place() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["a", "b"], "", "https://second"),
+        ];
+        let (mut merged, origins) = KubeConfig::merge(&files);
+        merged.clusters[0].cluster.server = "https://changed".to_string();
+        merged.users.retain(|u| u.name != "a");
+
+        let split = merged.split(&files, &origins);
+        assert_eq!(split[0].clusters[0].cluster.server, "https://changed");
+        assert_eq!(names(&split[0].users), Vec::<&str>::new());
+        // kubectl ignores file 1's `a`, so it's untouched
+        assert_eq!(split[1].clusters, files[1].clusters);
+        assert_eq!(split[1].users, files[1].users);
+    }
+
+    #[test]
+    fn new_entries_go_to_the_first_file() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["b"], "", "https://second"),
+        ];
+        let (mut merged, origins) = KubeConfig::merge(&files);
+        merged.contexts.push(NamedContext {
+            name: "c".to_string(),
+            context: ClusterContext::default(),
+            extra: Mapping::new(),
+        });
+
+        let split = merged.split(&files, &origins);
+        assert_eq!(names(&split[0].contexts), ["a", "c"]);
+        assert_eq!(names(&split[1].contexts), ["b"]);
+    }
+
+    #[test]
+    fn the_current_context_stays_in_the_file_that_sets_it() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["b"], "b", "https://second"),
+        ];
+        let (mut merged, origins) = KubeConfig::merge(&files);
+        merged.current_context = "a".to_string();
+
+        let split = merged.split(&files, &origins);
+        assert_eq!(split[0].current_context, "");
+        assert_eq!(split[1].current_context, "a");
+    }
+
+    #[test]
+    fn without_a_current_context_the_first_file_sets_it() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["b"], "", "https://second"),
+        ];
+        let (mut merged, origins) = KubeConfig::merge(&files);
+        merged.current_context = "b".to_string();
+
+        let split = merged.split(&files, &origins);
+        assert_eq!(split[0].current_context, "b");
+        assert_eq!(split[1].current_context, "");
+    }
+
+    #[test]
+    fn files_without_a_current_context_are_written_without_one() {
+        let files = [
+            kubeconfig(&["a"], "a", "https://first"),
+            parse("clusters:\n- name: b\n  cluster:\n    server: https://second\n"),
+        ];
+        let (merged, origins) = KubeConfig::merge(&files);
+
+        let split = merged.split(&files, &origins);
+        let yaml = serde_yml::to_string(&split[1]).unwrap();
+        assert!(!yaml.contains("current-context"), "{yaml}");
+        assert_eq!(parse(&yaml), files[1]);
+    }
+
+    #[test]
+    fn renamed_entries_keep_their_origin() {
+        let files = [
+            kubeconfig(&["a"], "", "https://first"),
+            kubeconfig(&["b", "c"], "", "https://second"),
+        ];
+        let (mut merged, mut origins) = KubeConfig::merge(&files);
+        // the way `kman rename` renames an entry
+        merged.users[1].name = "renamed".to_string();
+        let origin = origins.users.remove("b").unwrap();
+        origins.users.insert("renamed".to_string(), origin);
+
+        let split = merged.split(&files, &origins);
+        assert_eq!(names(&split[0].users), ["a"]);
+        assert_eq!(names(&split[1].users), ["renamed", "c"]);
+    }
+
+    #[test]
+    fn rebase_keeps_entries_added_on_disk() {
+        let base = kubeconfig(&["a", "b"], "a", "https://base");
+        let mut changed = base.clone();
+        changed.users[0].user.token = Some("kman".to_string());
+        let on_disk = kubeconfig(&["a", "b", "c"], "a", "https://base");
+
+        let rebased = changed.rebase(&base, &on_disk);
+        assert_eq!(names(&rebased.users), ["a", "b", "c"]);
+        assert_eq!(rebased.users[0].user.token.as_deref(), Some("kman"));
+        assert_eq!(rebased.contexts, on_disk.contexts);
+    }
+
+    #[test]
+    fn rebase_removes_entries_kman_removed() {
+        let base = kubeconfig(&["a", "b"], "a", "https://base");
+        let mut changed = base.clone();
+        changed.contexts.retain(|c| c.name != "b");
+        let on_disk = kubeconfig(&["a", "b", "c"], "a", "https://base");
+
+        let rebased = changed.rebase(&base, &on_disk);
+        assert_eq!(names(&rebased.contexts), ["a", "c"]);
+        assert_eq!(names(&rebased.users), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn rebase_prefers_kmans_changes() {
+        let base = kubeconfig(&["a", "b"], "a", "https://base");
+        let mut changed = base.clone();
+        changed.users[0].user.token = Some("kman".to_string());
+        changed.current_context = "b".to_string();
+        let mut on_disk = base.clone();
+        on_disk.users[0].user.token = Some("other".to_string());
+        on_disk.users[1].user.token = Some("other".to_string());
+
+        let rebased = changed.rebase(&base, &on_disk);
+        assert_eq!(rebased.users[0].user.token.as_deref(), Some("kman"));
+        // changed on disk only
+        assert_eq!(rebased.users[1].user.token.as_deref(), Some("other"));
+        assert_eq!(rebased.current_context, "b");
+    }
+
+    #[test]
+    fn rebase_keeps_the_current_context_set_on_disk() {
+        let base = kubeconfig(&["a", "b"], "a", "https://base");
+        let mut changed = base.clone();
+        changed.users[0].user.token = Some("kman".to_string());
+        let on_disk = kubeconfig(&["a", "b"], "b", "https://base");
+
+        assert_eq!(changed.rebase(&base, &on_disk).current_context, "b");
+    }
 }
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use roxygen::roxygen;
+use std::{
+    fs::OpenOptions,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for another program to release its lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An advisory lock on a kubeconfig file, the same `<file>.lock` that kubectl & oc (client-go) take while writing.
+/// The lock is released when this is dropped
+pub struct FileLock {
+    /// The location of the lock file
+    path: PathBuf,
+}
+
+impl FileLock {
+    #[roxygen]
+    /// Lock the given file, waiting a few seconds if another program holds the lock
+    pub fn acquire(
+        /// The file to lock
+        location: &Path,
+    ) -> Result<FileLock> {
+        let mut path = location.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(FileLock { path }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if start.elapsed() > LOCK_TIMEOUT {
+                        bail!(
+                            "Could not lock {}, {} exists. If no other kubectl, oc or kman is running, remove it and try again",
+                            location.display(),
+                            path.display()
+                        );
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Could not create {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_lock_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config");
+        let lock_file = dir.path().join("config.lock");
+
+        let lock = FileLock::acquire(&config).unwrap();
+        assert!(lock_file.exists());
+        drop(lock);
+        assert!(!lock_file.exists());
+
+        // and can be taken again afterwards
+        let _lock = FileLock::acquire(&config).unwrap();
+    }
+
+    #[test]
+    fn times_out_while_another_program_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config");
+        let lock_file = dir.path().join("config.lock");
+        std::fs::write(&lock_file, "").unwrap();
+
+        let start = Instant::now();
+        let error = FileLock::acquire(&config).err().unwrap();
+        assert!(start.elapsed() >= LOCK_TIMEOUT);
+        assert!(error.to_string().contains("config.lock exists"), "{error}");
+        // the lock isn't ours to remove
+        assert!(lock_file.exists());
+    }
+}
@@ -0,0 +1,227 @@
+use roxygen::roxygen;
+
+/// What kman can take from an `oc login` command, or the "Display Token" page of the OpenShift console
+#[derive(Debug, PartialEq)]
+pub struct LoginCommand {
+    /// The token to log in with
+    pub token: String,
+    /// The API server to log in to, if it was given
+    pub server: Option<String>,
+    /// Whether `--insecure-skip-tls-verify` was given
+    pub insecure_skip_tls_verify: bool,
+}
+
+#[roxygen]
+/// Parse an `oc login` command, or the whole text of the console's "Display Token" page.
+/// Returns `None` when the input contains neither
+pub fn parse(
+    /// The text that was pasted
+    input: &str,
+) -> Option<LoginCommand> {
+    input
+        .lines()
+        .find_map(parse_oc_login)
+        .or_else(|| parse_token_page(input))
+}
+
+/// The flags of `oc login` (and oc's global flags) that take a value, which is not the server when it follows without `=`
+const VALUE_FLAGS: &[&str] = &[
+    "--as",
+    "--as-group",
+    "--as-uid",
+    "--cache-dir",
+    "--callback-port",
+    "--certificate-authority",
+    "--client-certificate",
+    "--client-id",
+    "--client-key",
+    "--cluster",
+    "--context",
+    "--exec-plugin",
+    "--extra-scopes",
+    "--issuer-url",
+    "--kubeconfig",
+    "--loglevel",
+    "--namespace",
+    "--password",
+    "--request-timeout",
+    "--tls-server-name",
+    "--user",
+    "--username",
+    "-n",
+    "-p",
+    "-u",
+    "-v",
+];
+
+/// Parse a single line holding an `oc login` command
+fn parse_oc_login(line: &str) -> Option<LoginCommand> {
+    let start = line.find("oc login")?;
+    let words = shell_words::split(&line[start..]).ok()?;
+
+    let mut token = None;
+    let mut server = None;
+    let mut insecure_skip_tls_verify = false;
+
+    let mut args = words.into_iter().skip(2);
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with('-') => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+
+        match flag.as_str() {
+            "--token" => token = value.or_else(|| args.next()),
+            "--server" | "-s" => server = value.or_else(|| args.next()),
+            "--insecure-skip-tls-verify" => {
+                insecure_skip_tls_verify = value.is_none_or(|v| v == "true")
+            }
+            flag if value.is_none() && VALUE_FLAGS.contains(&flag) => {
+                args.next();
+            }
+            positional if !positional.starts_with('-') && server.is_none() => {
+                server = Some(positional.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    Some(LoginCommand {
+        token: token?,
+        server,
+        insecure_skip_tls_verify,
+    })
+}
+
+/// Parse the "Display Token" page for when it doesn't hold an `oc login` command.
+/// The token follows "Your API token is", the server can be taken from the example `curl` command
+fn parse_token_page(input: &str) -> Option<LoginCommand> {
+    let mut lines = input.lines().map(str::trim);
+    lines.find(|l| l.starts_with("Your API token is"))?;
+    let token = lines.find(|l| !l.is_empty())?.to_string();
+
+    let server = input
+        .lines()
+        .filter(|l| l.contains("curl"))
+        .flat_map(|l| l.split(['"', '\'', ' ']))
+        .find(|word| word.starts_with("https://"))
+        .map(|url| match url["https://".len()..].find('/') {
+            Some(path) => url[.."https://".len() + path].to_string(),
+            None => url.to_string(),
+        });
+
+    Some(LoginCommand {
+        token,
+        server,
+        insecure_skip_tls_verify: false,
+    })
+}
+
+#[roxygen]
+/// Whether two API server URLs point to the same server, ignoring case and trailing slashes
+pub fn same_server(
+    /// The first server URL
+    a: &str,
+    /// The second server URL
+    b: &str,
+) -> bool {
+    a.trim_end_matches('/')
+        .eq_ignore_ascii_case(b.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &str = "https://api.cluster.example.com:6443";
+
+    fn login(token: &str, server: Option<&str>) -> Option<LoginCommand> {
+        Some(LoginCommand {
+            token: token.to_string(),
+            server: server.map(str::to_string),
+            insecure_skip_tls_verify: false,
+        })
+    }
+
+    #[test]
+    fn parses_the_copied_login_command() {
+        assert_eq!(
+            parse("oc login --token=sha256~abc --server=https://api.cluster.example.com:6443"),
+            login("sha256~abc", Some(SERVER))
+        );
+    }
+
+    #[test]
+    fn parses_the_display_token_page() {
+        let page = "Your API token is\nsha256~abc\n\nLog in with this token\noc login --token=sha256~abc --server=https://api.cluster.example.com:6443\n\nUse this token directly against the API\ncurl -H \"Authorization: Bearer sha256~abc\" \"https://api.cluster.example.com:6443/apis/user.openshift.io/v1/users/~\"\n\nRequest another token\n\nLogout\n";
+        assert_eq!(parse(page), login("sha256~abc", Some(SERVER)));
+
+        // without the `oc login` command, the server comes from the curl example
+        let page = page.replace("oc login", "");
+        assert_eq!(parse(&page), login("sha256~abc", Some(SERVER)));
+    }
+
+    #[test]
+    fn parses_flags_with_and_without_equals_signs() {
+        let expected = login("sha256~abc", Some(SERVER));
+        assert_eq!(
+            parse("$ oc login --token sha256~abc --server https://api.cluster.example.com:6443"),
+            expected
+        );
+        assert_eq!(
+            parse("oc login -s https://api.cluster.example.com:6443 --token=sha256~abc"),
+            expected
+        );
+        assert_eq!(
+            parse("oc login -s=https://api.cluster.example.com:6443 --token sha256~abc"),
+            expected
+        );
+        assert_eq!(
+            parse("oc login https://api.cluster.example.com:6443 --token sha256~abc"),
+            expected
+        );
+        assert_eq!(
+            parse("oc login --token sha256~abc"),
+            login("sha256~abc", None)
+        );
+    }
+
+    #[test]
+    fn skips_the_values_of_other_flags() {
+        assert_eq!(
+            parse("oc login --certificate-authority ca.crt --token=sha256~abc"),
+            login("sha256~abc", None)
+        );
+        assert_eq!(
+            parse("oc login -u admin --certificate-authority ca.crt https://api.cluster.example.com:6443 --token=sha256~abc"),
+            login("sha256~abc", Some(SERVER))
+        );
+    }
+
+    #[test]
+    fn parses_insecure_skip_tls_verify() {
+        let insecure = |command| parse(command).unwrap().insecure_skip_tls_verify;
+        assert!(insecure("oc login --token=t --insecure-skip-tls-verify"));
+        assert!(insecure(
+            "oc login --token=t --insecure-skip-tls-verify=true"
+        ));
+        assert!(!insecure(
+            "oc login --token=t --insecure-skip-tls-verify=false"
+        ));
+        assert!(!insecure("oc login --token=t"));
+    }
+
+    #[test]
+    fn needs_a_token() {
+        assert_eq!(parse("oc login https://api.cluster.example.com:6443"), None);
+        assert_eq!(parse("sha256~abc"), None);
+    }
+
+    #[test]
+    fn compares_servers() {
+        assert!(same_server(SERVER, "https://API.cluster.example.com:6443/"));
+        assert!(!same_server(SERVER, "https://api.other.example.com:6443"));
+    }
+}
@@ -1,20 +1,45 @@
 use anyhow::{bail, Context, Ok, Result};
+use backup::{Backup, BackupFile};
+use base64::prelude::*;
+use client::TokenStatus;
 use colored::Colorize;
-use dialoguer::{theme::ColorfulTheme, Input, MultiSelect, Select};
+use config::Config;
+use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Password, Select};
 use human_panic::{setup_panic, Metadata};
-use kubeconfig::{KubeConfig, NamedContext};
-use regex::Regex;
+use kubeconfig::{
+    Cluster, ClusterContext, KubeConfig, NamedCluster, NamedContext, NamedUser, Origins, User,
+};
+use lock::FileLock;
+use log::debug;
+use login_command::LoginCommand;
+use oauth::OAuthToken;
 use roxygen::roxygen;
+use serde_yml::Mapping;
+use state::{State, TokenExpiry};
 use std::{
-    fs::File,
-    io::Write,
+    collections::HashMap,
+    io::{IsTerminal, Read},
     path::{Path, PathBuf},
 };
+use x509_parser::{pem::Pem, time::ASN1Time};
 
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use directories::BaseDirs;
 
+mod atomic;
+mod backup;
+mod client;
+mod config;
+mod diff;
 mod kubeconfig;
+mod lock;
+mod login_command;
+#[cfg(test)]
+mod mock_server;
+mod oauth;
+mod state;
+mod token;
+mod yaml_patch;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None, arg_required_else_help = true)]
@@ -22,6 +47,10 @@ struct Cli {
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 
+    /// Show how the kubeconfig would change, without changing anything on disk
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -29,37 +58,244 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Lists all contexts
-    List {},
+    List {
+        /// Don't check whether the clusters still accept the tokens
+        #[clap(long)]
+        no_check: bool,
+    },
     /// Select context to use
     Select {
-        /// The context name
+        /// The context name, or `-` to go back to the previous context
+        name: Option<String>,
+        /// Don't check whether the cluster still accepts the token
+        #[clap(long)]
+        no_check: bool,
+    },
+    /// Set the namespace of the current (or given) context
+    Ns {
+        /// The namespace to use, picked from the namespaces on the cluster when omitted
         name: Option<String>,
+        /// The context to change, instead of the current one
+        #[clap(short, long)]
+        context: Option<String>,
     },
+    /// Show the contexts selected most recently
+    History {
+        /// How many selections to show
+        #[clap(short = 'n', long, default_value_t = 10)]
+        count: usize,
+    },
+    /// Check which contexts have a token their cluster still accepts
+    Status {},
     /// Refresh context token
     Refresh {
         /// The context name
         #[clap(short, long)]
         name: Option<String>,
-        // TODO: add `--all` option
+        /// Refresh every context
+        #[clap(short, long, conflicts_with = "name")]
+        all: bool,
+        /// Only refresh contexts whose token the cluster no longer accepts
+        #[clap(long, requires = "all")]
+        expired_only: bool,
+        /// Only refresh contexts whose name matches this glob (e.g. `prod-*`)
+        #[clap(long, requires = "all")]
+        filter: Option<String>,
+        /// Log in with a username & password through the cluster's OAuth server, instead of pasting a token
+        #[clap(short, long)]
+        login: bool,
+        /// The username to log in with (defaults to `KMAN_USERNAME`)
+        #[clap(short, long, requires = "login")]
+        username: Option<String>,
+        /// Log in through the cluster's OAuth server in your browser, instead of pasting a token
+        #[clap(short, long, conflicts_with = "login")]
+        browser: bool,
+    },
+    /// Remove context(s), along with the users and clusters no other context uses
+    Remove {
+        /// Keep the users and clusters that no context uses anymore
+        #[clap(long)]
+        keep_orphans: bool,
+    },
+    /// Remove the users and clusters that no context uses
+    Prune {},
+    /// Add a new context, together with a cluster and user of the same name
+    Add {
+        /// The name of the new context, defaults to one derived from the server's hostname
+        name: Option<String>,
+        /// The cluster's API server URL (e.g. `https://api.example.com:6443`), prompts for everything else when omitted
+        #[clap(short, long)]
+        server: Option<String>,
+        /// The default namespace of the new context
+        #[clap(long)]
+        namespace: Option<String>,
+        /// A PEM file with the cluster's certificate authority, stored in the kubeconfig
+        #[clap(long)]
+        certificate_authority: Option<PathBuf>,
+        /// Don't verify the server's certificate
+        #[clap(long, conflicts_with = "certificate_authority")]
+        insecure_skip_tls_verify: bool,
+        /// The token to log in with, add it later with `kman refresh` when omitted
+        #[clap(long)]
+        token: Option<String>,
+    },
+    /// Import a token from an `oc login` command or the console's "Display Token" page
+    ImportLogin {
+        /// The `oc login` command, read from stdin when omitted
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Rename a context, or a user or cluster along with every context that refers to it
+    Rename {
+        /// The current name
+        old: String,
+        /// The new name
+        new: String,
+        /// Rename a user instead of a context
+        #[clap(long, conflicts_with = "cluster")]
+        user: bool,
+        /// Rename a cluster instead of a context
+        #[clap(long)]
+        cluster: bool,
+    },
+    /// Give the contexts created by `oc login` a friendly name, or move their token to an existing context for the same server
+    Adopt {},
+    /// Inspect the backups kman takes before changing the kubeconfig
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommands,
+    },
+    /// Restore the kubeconfig from the most recent (or the given) backup
+    Undo {
+        /// The id of the backup to restore
+        id: Option<String>,
+    },
+    /// Inspect clusters
+    Cluster {
+        #[command(subcommand)]
+        command: ClusterCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ClusterCommands {
+    /// Show a cluster's connection settings and certificate authority
+    Show {
+        /// The cluster name
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupsCommands {
+    /// List the backups, newest first
+    List {},
+    /// Show what changed in the kubeconfig since a backup was taken
+    Diff {
+        /// The id of the backup
+        id: String,
+    },
+}
+
+/// A single kubeconfig file as it was loaded from disk
+struct KubeConfigFile {
+    /// The location of the file
+    path: PathBuf,
+    /// The contents of the file as they were read
+    contents: String,
+    /// The parsed contents of the file
+    kubeconfig: KubeConfig,
+}
+
+/// The kind of kubeconfig entry to rename
+#[derive(Clone, Copy)]
+enum EntryKind {
+    /// An entry in `contexts`
+    Context,
+    /// An entry in `users`
+    User,
+    /// An entry in `clusters`
+    Cluster,
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::Context => write!(f, "context"),
+            EntryKind::User => write!(f, "user"),
+            EntryKind::Cluster => write!(f, "cluster"),
+        }
+    }
+}
+
+/// How a new token is obtained when refreshing a context
+#[derive(Clone)]
+enum RefreshMethod {
+    /// Paste a token requested in the web console
+    Paste,
+    /// Log in through the cluster's OAuth server with a username & password
+    Login {
+        /// The username, prompted for when not given
+        username: Option<String>,
     },
-    /// Remove context(s)
-    Remove {},
+    /// Log in through the cluster's OAuth server in the browser, for SSO identity providers
+    Browser,
+}
+
+/// The command backups taken by `kman undo` are recorded under
+const UNDO_COMMAND: &str = "undo";
+
+/// How long tokens live when kman doesn't know their expiry, OpenShift's default lifetime of 24 hours
+const DEFAULT_TOKEN_LIFETIME: u64 = 24 * 3_600;
+
+/// The outcome of refreshing a single context with `kman refresh --all`
+enum RefreshOutcome {
+    /// The token was refreshed
+    Refreshed,
+    /// The context did not need, or could not get, a refresh
+    Skipped(String),
+    /// Refreshing the token failed
+    Failed(String),
 }
 
 /// Struct used for state management
 struct Kman {
-    /// The parsed kubeconfig from the user's home directory
+    /// The merged kubeconfig, as kubectl sees it
     kubeconfig: KubeConfig,
+    /// The files the kubeconfig was merged from, in `KUBECONFIG` order
+    files: Vec<KubeConfigFile>,
+    /// Which file every cluster, user and context came from
+    origins: Origins,
+    /// kman's own state, such as when tokens were stored
+    state: State,
+    /// kman's configuration
+    config: Config,
+    /// Whether the state changed, it is saved once the kubeconfig is written
+    state_modified: bool,
 }
 
 impl Kman {
     #[roxygen]
     /// Create a new instance of [Kman]
     fn new(
-        /// The kubeconfig loaded from disk
-        kubeconfig: KubeConfig,
+        /// The kubeconfig files loaded from disk
+        files: Vec<KubeConfigFile>,
+        /// kman's own state
+        state: State,
+        /// kman's configuration
+        config: Config,
     ) -> Self {
-        Self { kubeconfig }
+        let kubeconfigs: Vec<KubeConfig> = files.iter().map(|f| f.kubeconfig.clone()).collect();
+        let (kubeconfig, origins) = KubeConfig::merge(&kubeconfigs);
+
+        Self {
+            kubeconfig,
+            files,
+            origins,
+            state,
+            config,
+            state_modified: false,
+        }
     }
 
     /// Get all the context in the user's kubeconfig
@@ -71,22 +307,126 @@ impl Kman {
             .collect()
     }
 
+    #[roxygen]
+    /// Describe contexts for a picker, with each context's server and namespace in aligned columns
+    fn context_labels(
+        &self,
+        /// The contexts to describe
+        contexts: &[String],
+    ) -> Vec<String> {
+        let servers: Vec<&str> = contexts
+            .iter()
+            .map(|name| {
+                self.get_cluster_from_context_name(name)
+                    .map(|cluster| cluster.server.as_str())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let namespaces: Vec<&str> = contexts
+            .iter()
+            .map(|name| {
+                self.kubeconfig
+                    .contexts
+                    .iter()
+                    .find(|ctx| &ctx.name == name)
+                    .and_then(|ctx| ctx.context.namespace.as_deref())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let name_width = contexts.iter().map(|c| c.len()).max().unwrap_or_default();
+        let server_width = servers.iter().map(|s| s.len()).max().unwrap_or_default();
+        contexts
+            .iter()
+            .zip(&servers)
+            .zip(&namespaces)
+            .map(|((name, server), namespace)| {
+                format!("{name:<name_width$}  {server:<server_width$}  {namespace}")
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[roxygen]
+    /// Let the user pick a context from a list they can filter by typing, showing the server and namespace of each one.
+    /// The current context is selected to begin with
+    fn pick_context(
+        &self,
+        /// The question to ask
+        prompt: &str,
+        /// The contexts to pick from
+        contexts: &[String],
+        /// The text to filter the list with to begin with
+        query: &str,
+    ) -> Result<String> {
+        if contexts.is_empty() {
+            bail!("There are no contexts to pick from");
+        }
+        // unlike `Select`, `FuzzySelect` doesn't notice it can't read keys and would wait forever
+        if !std::io::stdin().is_terminal() {
+            bail!("Can't pick a context without a terminal, run kman in one or pass the context's name where the command takes it");
+        }
+
+        let items = self.context_labels(contexts);
+        let selected_index = FuzzySelect::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .with_initial_text(query)
+            .default(
+                contexts
+                    .iter()
+                    .position(|c| *c == self.kubeconfig.current_context)
+                    .filter(|_| query.is_empty())
+                    .unwrap_or(0),
+            )
+            .items(&items)
+            .interact()?;
+
+        Ok(contexts[selected_index].clone())
+    }
+
+    #[roxygen]
     /// This returns the current existing contexts,
     /// and shows which is active
-    fn list_contexts(&self) -> Result<String> {
+    fn list_contexts(
+        &self,
+        /// Whether to check if the clusters still accept the tokens
+        check: bool,
+    ) -> Result<String> {
         if self.kubeconfig.contexts.is_empty() {
             bail!("Kubeconfig does not contain any contexts");
         }
 
+        let statuses = if check {
+            self.check_all_contexts()
+        } else {
+            HashMap::new()
+        };
+        let width = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .map(|c| c.name.len())
+            .max()
+            .unwrap_or(0);
+
         let mut out = String::new();
 
         for ctx in &self.kubeconfig.contexts {
             if ctx.name == self.kubeconfig.current_context {
                 out.push_str(&format!("{}", "* ".green().bold()));
-                out.push_str(&format!("{}", ctx.name.green()));
+                out.push_str(&format!("{}", format!("{:width$}", ctx.name).green()));
             } else {
-                out.push_str(&ctx.name);
+                out.push_str(&format!("  {:width$}", ctx.name));
             }
+            if let Some(status) = statuses.get(&ctx.name) {
+                out.push_str(&format!("  {}", Self::describe_status(status)));
+            }
+            if let Some(expiry) = self.describe_expiry(&ctx.name) {
+                out.push_str(&format!("  {}", expiry));
+            }
+            let trimmed = out.trim_end().len();
+            out.truncate(trimmed);
             out.push('\n');
         }
 
@@ -94,225 +434,2351 @@ impl Kman {
     }
 
     #[roxygen]
-    /// Updates the kubeconfig's current-context to the given context name
-    // TODO: add auto-check for expired credentials
-    fn select_context(
-        &mut self,
-        /// The context name to use
-        context_name: String,
-    ) -> Result<()> {
-        let mut found = false;
-        for ctx in &self.kubeconfig.contexts {
-            if ctx.name == context_name {
-                self.kubeconfig.current_context = context_name.clone();
-                found = true;
-            }
-        }
+    /// Check whether the cluster of a context still accepts its token.
+    /// Returns `None` when the context's user doesn't authenticate with a token
+    fn check_context(
+        &self,
+        /// The context to check
+        context_name: &str,
+    ) -> Option<TokenStatus> {
+        let token = self.get_token_from_context_name(context_name)?;
 
-        if !found {
-            bail!("Given context does not exist");
-        }
+        Some(match self.get_cluster_from_context_name(context_name) {
+            Result::Ok(cluster) => client::check_token(cluster, token),
+            Err(e) => TokenStatus::Unreachable(e.to_string()),
+        })
+    }
 
-        println!("Now using context: {}", context_name.green().bold());
+    /// Check all contexts in parallel, so one unreachable cluster doesn't hold up the rest
+    fn check_all_contexts(&self) -> HashMap<String, Option<TokenStatus>> {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .kubeconfig
+                .contexts
+                .iter()
+                .map(|ctx| {
+                    let handle = scope.spawn(|| self.check_context(&ctx.name));
+                    (ctx.name.clone(), handle)
+                })
+                .collect();
 
-        Ok(())
+            handles
+                .into_iter()
+                .map(|(name, handle)| {
+                    let status = handle.join().unwrap_or_else(|_| {
+                        Some(TokenStatus::Unreachable("check panicked".to_string()))
+                    });
+                    (name, status)
+                })
+                .collect()
+        })
     }
 
     #[roxygen]
-    /// Overwrite the user's kubeconfig with an updated one
-    fn update_kubeconfig(
-        &self,
-        /// The location of the kubeconfig to override
-        kubeconfig_location: &PathBuf,
-    ) -> Result<()> {
-        // TODO: add context to these `?`'s
-        let yaml = serde_yml::to_string(&self.kubeconfig)?;
-        let mut kubeconfig = File::create(kubeconfig_location)?;
-        kubeconfig.write_all(yaml.as_bytes())?;
+    /// Describe the result of [Kman::check_context] for humans
+    fn describe_status(
+        /// The status to describe
+        status: &Option<TokenStatus>,
+    ) -> String {
+        match status {
+            Some(TokenStatus::Valid(Some(username))) => {
+                format!("{} (as {})", "valid".green(), username)
+            }
+            Some(TokenStatus::Valid(None)) => "valid".green().to_string(),
+            Some(TokenStatus::Expired) => "expired".red().to_string(),
+            Some(TokenStatus::Unreachable(reason)) => {
+                format!("{} ({})", "unreachable".yellow(), reason)
+            }
+            None => "not checked, no token".dimmed().to_string(),
+        }
+    }
 
-        Ok(())
+    #[roxygen]
+    /// Describe when the token of a context expires, as far as kman can tell without asking the cluster
+    fn describe_expiry(
+        &self,
+        /// The context to describe
+        context_name: &str,
+    ) -> Option<String> {
+        let now = token::now();
+        match self.get_token_expiry(context_name)? {
+            TokenExpiry::ExpiresAt(expires_at) if expires_at > now => Some(
+                format!("expires in {}", token::format_duration(expires_at - now))
+                    .dimmed()
+                    .to_string(),
+            ),
+            TokenExpiry::ExpiresAt(expires_at) => Some(
+                format!("expired {} ago", token::format_duration(now - expires_at))
+                    .red()
+                    .to_string(),
+            ),
+            TokenExpiry::IssuedAt(issued_at) => {
+                let age = now.saturating_sub(issued_at);
+                let description = format!("refreshed {} ago", token::format_duration(age));
+                if age > DEFAULT_TOKEN_LIFETIME {
+                    Some(format!("{description}, likely stale").yellow().to_string())
+                } else {
+                    Some(description.dimmed().to_string())
+                }
+            }
+        }
     }
 
     #[roxygen]
-    /// Get a "user" from the given context name
-    fn get_user_from_context_name(
+    /// Get when the token of a context expires, as far as kman can tell without asking the cluster
+    fn get_token_expiry(
         &self,
-        /// The context name to use
-        context_name: String,
-    ) -> Result<String> {
-        Ok(self
+        /// The context to look up
+        context_name: &str,
+    ) -> Option<TokenExpiry> {
+        let user_name = self
+            .get_user_from_context_name(context_name.to_string())
+            .ok()?;
+        let token = self
             .kubeconfig
-            .contexts
+            .users
             .iter()
-            .find(|c| c.name == context_name)
-            .context("Given context does not exist")?
-            .context
+            .find(|u| u.name == user_name)?
             .user
-            .clone())
+            .token
+            .as_ref()?;
+
+        self.state.token_expiry(token)
     }
 
-    #[roxygen]
-    /// Update a context token based on user input
-    fn update_token(
-        &mut self,
-        /// The context name to use
-        context_name: Option<String>,
-    ) -> Result<()> {
-        let context_to_update = context_name.unwrap_or(self.kubeconfig.current_context.clone());
+    /// This returns the token status of every context
+    fn context_statuses(&self) -> Result<String> {
+        if self.kubeconfig.contexts.is_empty() {
+            bail!("Kubeconfig does not contain any contexts");
+        }
 
-        let user = self.get_user_from_context_name(context_to_update)?;
+        let statuses = self.check_all_contexts();
+        let width = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .map(|c| c.name.len())
+            .max()
+            .unwrap_or(0);
 
-        let token: String = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Request a token (sha256~xxx...) in the console and paste it in here:")
-            .interact_text()?;
+        let mut out = String::new();
+        for ctx in &self.kubeconfig.contexts {
+            let status = statuses.get(&ctx.name).unwrap_or(&None);
+            out.push_str(&format!(
+                "{:width$}  {}\n",
+                ctx.name,
+                Self::describe_status(status)
+            ));
+        }
 
-        let token_regex =
-            Regex::new(r"^sha256~[a-zA-Z0-9_-]{43}$").context("failed to compile regex")?;
+        Ok(out)
+    }
 
-        if token_regex.is_match(&token) {
-            for u in &mut self.kubeconfig.users {
-                if u.name == user {
-                    u.user.token = token;
-                    break;
-                }
-            }
-        } else {
-            bail!("Incorrect token given. A token looks like this: `sha256~re5x9PB4OYjn7BLUubSiWkHBYg6QdyflL1-4jcIJvmQ`");
+    #[roxygen]
+    /// Describe a cluster, including the subject, issuer and expiry of its certificate authorities
+    fn show_cluster(
+        &self,
+        /// The name of the cluster to describe
+        cluster_name: &str,
+    ) -> Result<String> {
+        let cluster = &self
+            .kubeconfig
+            .clusters
+            .iter()
+            .find(|c| c.name == cluster_name)
+            .context("Given cluster does not exist")?
+            .cluster;
+
+        let mut out = String::new();
+        out.push_str(&format!("{} {}\n", "Cluster:".bold(), cluster_name));
+        out.push_str(&format!("{} {}\n", "Server:".bold(), cluster.server));
+        if let Some(tls_server_name) = &cluster.tls_server_name {
+            out.push_str(&format!(
+                "{} {}\n",
+                "TLS server name:".bold(),
+                tls_server_name
+            ));
+        }
+        if let Some(proxy_url) = &cluster.proxy_url {
+            out.push_str(&format!("{} {}\n", "Proxy URL:".bold(), proxy_url));
+        }
+        if cluster.insecure_skip_tls_verify == Some(true) {
+            out.push_str(&format!(
+                "{} {}\n",
+                "TLS verification:".bold(),
+                "disabled".red()
+            ));
         }
 
-        println!("{}", "Token updated succesfully!".green().bold());
+        let Some(pem) = cluster.certificate_authority_pem()? else {
+            out.push_str(&format!(
+                "{} none configured (system trust store is used)\n",
+                "Certificate authority:".bold()
+            ));
+            return Ok(out);
+        };
 
-        Ok(())
+        let now = ASN1Time::now();
+        for (index, pem) in Pem::iter_from_buffer(pem.as_bytes()).enumerate() {
+            let pem = pem.context("Certificate authority contains invalid PEM data")?;
+            let certificate = pem
+                .parse_x509()
+                .context("Certificate authority contains an invalid certificate")?;
+            let not_after = certificate.validity().not_after;
+            let expiry = if not_after < now {
+                format!("{} (expired)", not_after).red().to_string()
+            } else {
+                not_after.to_string()
+            };
+
+            out.push_str(&format!(
+                "\n{} #{}\n",
+                "Certificate authority".bold(),
+                index + 1
+            ));
+            out.push_str(&format!("  Subject: {}\n", certificate.subject()));
+            out.push_str(&format!("  Issuer:  {}\n", certificate.issuer()));
+            out.push_str(&format!("  Expires: {}\n", expiry));
+        }
+
+        Ok(out)
     }
 
-    #[roxygen]
-    /// Load a kubeconfig from disk
-    fn load_kubeconfig(
-        /// The kubeconfig file location
-        kubeconfig_location: &PathBuf,
-    ) -> Result<KubeConfig> {
-        let kubeconfig_str = std::fs::read_to_string(kubeconfig_location)
-            .context("Could not read kubeconfig file")?;
-        let kubeconfig: KubeConfig =
-            serde_yml::from_str(&kubeconfig_str).context("Given file is not a valid Kubeconfig")?;
-        Ok(kubeconfig)
+    /// The context that was active before the last `kman select`, to go back to with `kman select -`
+    fn previous_context(&self) -> Result<String> {
+        self.state
+            .previous_context
+            .clone()
+            .context("There is no previous context to go back to yet")
     }
 
     #[roxygen]
-    /// Remove a context from the kubeconfig based on it's name
-    fn remove_context(
-        &mut self,
-        /// The name of the context to remove
-        context_name_to_remove: &str,
-    ) -> Result<()> {
-        // TODO: clean up/remove `users` and `clusters` based on
-        // whether we are removing the final `context` for said cluster/user.
-        // not implemented right now because it technically does not matter,
-        // it would just be proper to do so :)
-        // TODO: notify user if we are removing current context
-        let remaining_contexts: Vec<NamedContext> = self
-            .kubeconfig
-            .contexts
-            .clone()
-            .into_iter()
-            .filter(|c| c.name != context_name_to_remove)
-            .collect();
+    /// List the contexts selected most recently, newest first
+    fn history(
+        &self,
+        /// How many selections to list
+        count: usize,
+    ) -> Result<String> {
+        if self.state.history.is_empty() {
+            bail!("No contexts have been selected with kman yet");
+        }
 
-        self.kubeconfig.contexts = remaining_contexts;
-        Ok(())
-    }
-}
+        let now = token::now();
+        let mut out = String::new();
+        for selection in self.state.history.iter().rev().take(count) {
+            let context = if selection.context == self.kubeconfig.current_context {
+                selection.context.green().bold()
+            } else {
+                selection.context.normal()
+            };
+            out.push_str(&format!(
+                "{:>9}  {}\n",
+                format!(
+                    "{} ago",
+                    token::format_duration(now.saturating_sub(selection.selected_at))
+                ),
+                context
+            ));
+        }
 
-fn main() -> Result<()> {
-    setup_panic!(
-        Metadata::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
-            .authors("Stef Robbe <stef.robbe@protonmail.com>")
-            .homepage("https://github.com/stef16robbe/kman")
-            .support("- Open an issue on the Github repo")
-    );
+        Ok(out)
+    }
 
-    let cli = Cli::parse();
-    env_logger::Builder::new()
-        .filter_level(cli.verbose.log_level_filter())
-        .init();
+    #[roxygen]
+    /// Find the context a (partial) name refers to: the context with exactly that name, or else the only context
+    /// that starts with, contains or fuzzily matches it. When several contexts match, the user picks one of them
+    fn find_context(
+        &self,
+        /// The (partial) context name
+        query: &str,
+        /// Whether the user can pick a context when several match
+        interactive: bool,
+    ) -> Result<String> {
+        let contexts = self.get_all_contexts();
+        if contexts.iter().any(|c| c == query) {
+            return Ok(query.to_string());
+        }
 
-    // TODO: remove `unwrap()`
-    let base_dirs = BaseDirs::new().unwrap();
-    let kubeconfig_location = std::env::var("KUBECONFIG")
-        .map(|v| v.into())
-        .unwrap_or_else(|_| base_dirs.home_dir().join(Path::new(".kube/config")));
+        let query_lowercase = query.to_lowercase();
+        let matchers: [&dyn Fn(&str) -> bool; 3] = [
+            &|name| name.starts_with(&query_lowercase),
+            &|name| name.contains(&query_lowercase),
+            &|name| is_subsequence(&query_lowercase, name),
+        ];
+        for matcher in matchers {
+            let matches: Vec<&String> = contexts
+                .iter()
+                .filter(|c| matcher(&c.to_lowercase()))
+                .collect();
+            match matches.as_slice() {
+                [] => continue,
+                [context] => return Ok(context.to_string()),
+                _ if interactive => {
+                    return self.pick_context(
+                        &format!("Multiple contexts match `{query}`, pick the one you want to use"),
+                        &contexts,
+                        query,
+                    );
+                }
+                _ => {
+                    let names: Vec<&str> = matches.iter().map(|c| c.as_str()).collect();
+                    bail!("`{query}` matches multiple contexts: {}", names.join(", "));
+                }
+            }
+        }
 
-    if !kubeconfig_location.exists() {
+        // suggest the contexts whose names are closest to what was typed
+        let mut suggestions: Vec<(usize, &String)> = contexts
+            .iter()
+            .map(|c| (edit_distance(&query_lowercase, &c.to_lowercase()), c))
+            .filter(|(distance, _)| *distance <= (query.chars().count() / 3).max(2))
+            .collect();
+        suggestions.sort();
+        let suggestions: Vec<String> = suggestions
+            .iter()
+            .take(3)
+            .map(|(_, c)| format!("`{c}`"))
+            .collect();
+        if suggestions.is_empty() {
+            bail!("Given context does not exist");
+        }
         bail!(
-            "No file found at: {}\nYou can specify a custom location with the `KUBECONFIG` environment variable",
-            // TODO: remove `unwrap()`
-            kubeconfig_location.to_str().unwrap()
-        );
+            "Given context does not exist, did you mean {}?",
+            suggestions.join(" or ")
+        )
     }
 
-    let kubeconfig = Kman::load_kubeconfig(&kubeconfig_location)?;
-    let mut kman = Kman::new(kubeconfig);
-
-    if let Some(command) = cli.command {
-        match command {
-            Commands::List {} => {
-                let contexts = kman.list_contexts()?;
-                println!(
-                    "{}\n\n{}",
-                    "Here are your current contexts:".bold(),
-                    contexts
-                );
+    #[roxygen]
+    /// Updates the kubeconfig's current-context to the given context name
+    fn select_context(
+        &mut self,
+        /// The context name to use
+        context_name: String,
+        /// Whether to check if the cluster still accepts the token
+        check: bool,
+    ) -> Result<()> {
+        let mut found = false;
+        let previous = self.kubeconfig.current_context.clone();
+        for ctx in &self.kubeconfig.contexts {
+            if ctx.name == context_name {
+                self.kubeconfig.current_context = context_name.clone();
+                found = true;
             }
-            Commands::Select { name } => {
-                let context_to_select = if let Some(name) = name {
-                    name
-                } else {
-                    // TODO: highlight current context in this menu
-                    let contexts = kman.get_all_contexts();
-                    let selected_index = Select::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Pick the context you want to use")
-                        .default(0)
-                        .items(&contexts)
-                        .interact()?;
-
-                    contexts[selected_index].clone()
-                };
+        }
 
-                kman.select_context(context_to_select)?;
-            }
-            Commands::Refresh { name } => kman.update_token(name)?,
-            Commands::Remove {} => {
-                // TODO: highlight current context in this menu
-                let contexts = kman.get_all_contexts();
-                let selected = MultiSelect::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Pick the context(s) you want to remove (`space` = select an item, `enter` = confirm selected)")
-                    .items(&contexts)
-                    .interact()?;
+        if !found {
+            bail!("Given context does not exist");
+        }
 
-                if selected.is_empty() {
-                    bail!("You didn't select any contexts. Press `space` to select.")
-                }
+        self.state.record_selection(&previous, &context_name);
+        self.state_modified = true;
 
-                let contexts_to_remove: Vec<String> = selected
-                    .iter()
-                    .filter_map(|&index| contexts.get(index).cloned())
-                    .collect();
+        println!("Now using context: {}", context_name.green().bold());
 
-                println!("{}", "Backing up your kubeconfig just in case...".green());
+        let status = if check {
+            self.check_context(&context_name)
+        } else {
+            None
+        };
 
-                let mut backup_kubeconfig_location = kubeconfig_location.clone();
-                backup_kubeconfig_location.set_extension("yaml.bak");
-                kman.update_kubeconfig(&backup_kubeconfig_location)?;
+        match status {
+            Some(TokenStatus::Expired) => println!(
+                "{}",
+                "The token for this context has expired, run `kman refresh` to get a new one"
+                    .yellow()
+            ),
+            Some(TokenStatus::Valid(_)) => {}
+            Some(TokenStatus::Unreachable(_)) | None => {
+                if let Some(TokenStatus::Unreachable(reason)) = &status {
+                    println!(
+                        "{}",
+                        format!("Could not check the token for this context: {reason}").yellow()
+                    );
+                }
 
-                for context_to_remove in contexts_to_remove {
-                    kman.remove_context(&context_to_remove)?;
-                    println!("Removed context {}!", context_to_remove);
+                // without an answer from the cluster, fall back to what we know about the token
+                let now = token::now();
+                match self.get_token_expiry(&context_name) {
+                    Some(TokenExpiry::ExpiresAt(expires_at)) if expires_at <= now => println!(
+                        "{}",
+                        format!(
+                            "The token for this context expired {} ago, run `kman refresh` to get a new one",
+                            token::format_duration(now - expires_at)
+                        )
+                        .yellow()
+                    ),
+                    Some(TokenExpiry::IssuedAt(issued_at))
+                        if now.saturating_sub(issued_at) > DEFAULT_TOKEN_LIFETIME =>
+                    {
+                        println!(
+                            "{}",
+                            format!(
+                                "The token for this context was refreshed {} ago and is likely stale, run `kman refresh` to get a new one",
+                                token::format_duration(now - issued_at)
+                            )
+                            .yellow()
+                        )
+                    }
+                    _ => {}
                 }
             }
         }
 
-        kman.update_kubeconfig(&kubeconfig_location)?;
+        Ok(())
     }
 
-    Ok(())
+    #[roxygen]
+    /// Set the namespace of a context, picking it from the namespaces on the cluster when none is given
+    fn set_namespace(
+        &mut self,
+        /// The context to change, the current context when `None`
+        context_name: Option<String>,
+        /// The namespace to use, an empty one removes the context's namespace
+        namespace: Option<String>,
+    ) -> Result<()> {
+        let context_name = context_name.unwrap_or_else(|| self.kubeconfig.current_context.clone());
+        if context_name.is_empty() {
+            bail!("There is no current context, pass one with `--context`");
+        }
+        let Some(ctx) = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .find(|ctx| ctx.name == context_name)
+        else {
+            bail!("Given context does not exist");
+        };
+        let current = ctx.context.namespace.clone();
+
+        let namespace = match namespace {
+            Some(namespace) => namespace,
+            None => self.pick_namespace(&context_name, current.as_deref())?,
+        };
+        if !namespace.is_empty() && !is_valid_namespace(&namespace) {
+            bail!(
+                "`{namespace}` is not a valid namespace name, it may only contain lowercase letters, digits and `-`"
+            );
+        }
+
+        let ctx = self
+            .kubeconfig
+            .contexts
+            .iter_mut()
+            .find(|ctx| ctx.name == context_name)
+            .context("Given context does not exist")?;
+        if namespace.is_empty() {
+            ctx.context.namespace = None;
+            println!(
+                "Context {} no longer has a default namespace",
+                context_name.green().bold()
+            );
+        } else {
+            println!(
+                "Now using namespace {} in context {}",
+                namespace.green().bold(),
+                context_name.green().bold()
+            );
+            ctx.context.namespace = Some(namespace);
+        }
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Ask which namespace to use, out of the ones the context's token may use.
+    /// When the cluster can't be asked, the namespace has to be typed in instead
+    fn pick_namespace(
+        &self,
+        /// The context to pick a namespace for
+        context_name: &str,
+        /// The namespace the context uses now
+        current: Option<&str>,
+    ) -> Result<String> {
+        let namespaces = self
+            .get_token_from_context_name(context_name)
+            .context("This context's user doesn't authenticate with a token")
+            .and_then(|token| {
+                client::list_namespaces(self.get_cluster_from_context_name(context_name)?, token)
+            });
+
+        match namespaces {
+            Result::Ok(namespaces) if !namespaces.is_empty() => {
+                let selected = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Pick the namespace you want to use")
+                    .default(
+                        current
+                            .and_then(|c| namespaces.iter().position(|n| n == c))
+                            .unwrap_or(0),
+                    )
+                    .items(&namespaces)
+                    .interact()?;
+                Ok(namespaces[selected].clone())
+            }
+            namespaces => {
+                if let Err(e) = namespaces {
+                    println!(
+                        "{}",
+                        format!("Could not list the namespaces on this cluster: {e:#}").yellow()
+                    );
+                }
+                Ok(Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Namespace to use (leave empty for none)")
+                    .with_initial_text(current.unwrap_or_default())
+                    .allow_empty(true)
+                    .interact_text()?)
+            }
+        }
+    }
+
+    #[roxygen]
+    /// Write the changes made to the kubeconfig back to disk.
+    /// Only the files that contain a modified entry are touched, each while holding its lock,
+    /// after backing them up. When a file was changed by another program in the meantime,
+    /// kman's changes are applied on top of it
+    fn update_kubeconfig(
+        &self,
+        /// The kman command that made the changes, for the backup
+        command: &str,
+    ) -> Result<()> {
+        let mut locks = Vec::new();
+        let mut backups = Vec::new();
+        let mut changed = Vec::new();
+        for (file, kubeconfig) in self.modified_files() {
+            if let Some(dir) = file.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                // a new kubeconfig may be the first file in its directory (e.g. `~/.kube`)
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Could not create {}", dir.display()))?;
+            }
+            locks.push(FileLock::acquire(&file.path)?);
+            let on_disk = std::fs::read_to_string(&file.path).ok();
+            let (yaml, formatting_lost) = match &on_disk {
+                Some(on_disk) if *on_disk != file.contents => {
+                    println!(
+                        "{}",
+                        format!(
+                            "{} was changed since kman read it, applying kman's changes on top of it",
+                            file.path.display()
+                        )
+                        .yellow()
+                    );
+                    let on_disk_kubeconfig = Self::parse_kubeconfig(on_disk, &file.path)?;
+                    let kubeconfig = kubeconfig.rebase(&file.kubeconfig, &on_disk_kubeconfig);
+                    Self::render_kubeconfig(on_disk, &on_disk_kubeconfig, &kubeconfig)?
+                }
+                _ => Self::render_kubeconfig(&file.contents, &file.kubeconfig, &kubeconfig)?,
+            };
+            if formatting_lost {
+                println!(
+                    "{}",
+                    format!(
+                        "Could not edit {} in place, it is rewritten as a whole: its comments and formatting are lost",
+                        file.path.display()
+                    )
+                    .yellow()
+                );
+            }
+
+            backups.push(BackupFile {
+                path: file.path.clone(),
+                contents: on_disk,
+            });
+            changed.push((yaml, &file.path));
+        }
+
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        Backup::save(command, backups, self.config.backups)?;
+        for (yaml, location) in changed {
+            atomic::write(location, yaml.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether any command changed the kubeconfig, so it has to be written back to disk
+    fn is_modified(&self) -> bool {
+        !self.modified_files().is_empty()
+    }
+
+    /// The files that contain a modified entry, together with what they should contain now
+    fn modified_files(&self) -> Vec<(&KubeConfigFile, KubeConfig)> {
+        let originals: Vec<KubeConfig> = self.files.iter().map(|f| f.kubeconfig.clone()).collect();
+
+        self.files
+            .iter()
+            .zip(self.kubeconfig.split(&originals, &self.origins))
+            .filter(|(file, kubeconfig)| *kubeconfig != file.kubeconfig)
+            .collect()
+    }
+
+    /// Describe how the kubeconfig files would change, with their secrets redacted
+    fn describe_changes(&self) -> Result<String> {
+        let mut out = String::new();
+        for (file, kubeconfig) in self.modified_files() {
+            let (yaml, formatting_lost) =
+                Self::render_kubeconfig(&file.contents, &file.kubeconfig, &kubeconfig)?;
+            if formatting_lost {
+                out.push_str(&format!(
+                    "{}\n",
+                    format!(
+                        "Could not edit {} in place, it would be rewritten as a whole: its comments and formatting would be lost",
+                        file.path.display()
+                    )
+                    .yellow()
+                ));
+            }
+            out.push_str(&diff::unified(
+                &diff::redact_secrets(&file.contents),
+                &diff::redact_secrets(&yaml),
+                &file.path.display().to_string(),
+                &format!("{} (dry run)", file.path.display()),
+            ));
+        }
+
+        if out.is_empty() {
+            out = "Dry run, kman would not change your kubeconfig\n".to_string();
+        }
+
+        Ok(out)
+    }
+
+    /// List the backups kman took of the kubeconfig
+    fn list_backups() -> Result<String> {
+        let backups = Backup::list()?;
+        if backups.is_empty() {
+            bail!("There are no backups yet, kman takes one every time it changes your kubeconfig");
+        }
+
+        let now = token::now();
+        let mut out = String::new();
+        for backup in backups {
+            let files: Vec<String> = backup
+                .files
+                .iter()
+                .map(|f| f.path.display().to_string())
+                .collect();
+            out.push_str(&format!(
+                "{}  {:>9}  {:<10}  {}\n",
+                backup.id.bold(),
+                format!(
+                    "{} ago",
+                    token::format_duration(now.saturating_sub(backup.created_at))
+                ),
+                backup.command,
+                files.join(", ")
+            ));
+        }
+
+        Ok(out)
+    }
+
+    #[roxygen]
+    /// Show how the kubeconfig files changed since a backup was taken
+    fn diff_backup(
+        /// The backup's id
+        id: &str,
+    ) -> Result<String> {
+        let backup = Backup::load(id)?;
+
+        let mut out = String::new();
+        for file in &backup.files {
+            let current = std::fs::read_to_string(&file.path).unwrap_or_default();
+            out.push_str(&diff::unified(
+                &diff::redact_secrets(file.contents.as_deref().unwrap_or_default()),
+                &diff::redact_secrets(&current),
+                &format!("{} (backup {})", file.path.display(), backup.id),
+                &file.path.display().to_string(),
+            ));
+        }
+
+        if out.is_empty() {
+            out = "Nothing changed since this backup was taken\n".to_string();
+        }
+
+        Ok(out)
+    }
+
+    #[roxygen]
+    /// Restore the kubeconfig files from a backup, by default the most recent one that wasn't taken by `undo` itself.
+    /// The files are backed up before they are restored, so the undo can be undone. The restored backup
+    /// is removed afterwards, so undoing again goes back another step
+    fn undo(
+        /// The backup's id
+        id: Option<String>,
+        /// Only show what would be restored
+        dry_run: bool,
+        /// How many backups to keep
+        retention: usize,
+    ) -> Result<()> {
+        let backup = match id {
+            Some(id) => Backup::load(&id)?,
+            None => Backup::list()?
+                .into_iter()
+                .find(|backup| backup.command != UNDO_COMMAND)
+                .context("There are no backups to restore, pass the id of a backup `kman undo` took to undo it")?,
+        };
+
+        print!("{}", Self::diff_backup(&backup.id)?);
+        if dry_run {
+            println!("Dry run, nothing was restored");
+            return Ok(());
+        }
+
+        let restore = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Restore the backup taken before `kman {}`?",
+                backup.command
+            ))
+            .default(false)
+            .interact()?;
+        if !restore {
+            bail!("Nothing was restored");
+        }
+
+        let mut locks = Vec::new();
+        let mut current = Vec::new();
+        for file in &backup.files {
+            locks.push(FileLock::acquire(&file.path)?);
+            current.push(BackupFile {
+                path: file.path.clone(),
+                contents: std::fs::read_to_string(&file.path).ok(),
+            });
+        }
+        let undo_backup = Backup::save(UNDO_COMMAND, current, retention)?;
+
+        for file in &backup.files {
+            match &file.contents {
+                Some(contents) => atomic::write(&file.path, contents.as_bytes())?,
+                // the command created the file
+                None if file.path.exists() => std::fs::remove_file(&file.path)
+                    .with_context(|| format!("Could not remove {}", file.path.display()))?,
+                None => {}
+            }
+        }
+        backup.remove()?;
+
+        println!("{}", "Restored your kubeconfig!".green().bold());
+        if let Some(id) = undo_backup {
+            println!("Run `kman undo {id}` to go back to how it was before");
+        }
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Turn a kubeconfig into the text of its file. When possible only the parts of the
+    /// original text that changed are edited, keeping its formatting and comments.
+    /// Otherwise the whole file is rewritten, returned along with whether that loses the file's formatting
+    fn render_kubeconfig(
+        /// The file's original text
+        original_text: &str,
+        /// The kubeconfig the original text holds
+        original: &KubeConfig,
+        /// The kubeconfig to write
+        kubeconfig: &KubeConfig,
+    ) -> Result<(String, bool)> {
+        let old = serde_yml::to_value(original).context("Could not serialize kubeconfig")?;
+        let new = serde_yml::to_value(kubeconfig).context("Could not serialize kubeconfig")?;
+
+        // only trust an edit that reads back as the kubeconfig kman meant to write
+        let patched = yaml_patch::patch(original_text, &old, &new).filter(|patched| {
+            serde_yml::from_str::<KubeConfig>(patched).is_ok_and(|k| k == *kubeconfig)
+        });
+        if let Some(patched) = patched {
+            return Ok((patched, false));
+        }
+
+        let original_yaml =
+            serde_yml::to_string(original).context("Could not serialize kubeconfig")?;
+        let formatting_lost = !original_text.trim().is_empty() && original_yaml != original_text;
+        let yaml = serde_yml::to_string(kubeconfig).context("Could not serialize kubeconfig")?;
+
+        Ok((yaml, formatting_lost))
+    }
+
+    #[roxygen]
+    /// Get the token of a context's user, if it authenticates with one
+    fn get_token_from_context_name(
+        &self,
+        /// The context name to use
+        context_name: &str,
+    ) -> Option<&str> {
+        let user_name = self
+            .get_user_from_context_name(context_name.to_string())
+            .ok()?;
+        self.kubeconfig
+            .users
+            .iter()
+            .find(|u| u.name == user_name)?
+            .user
+            .token
+            .as_deref()
+    }
+
+    #[roxygen]
+    /// Get a "user" from the given context name
+    fn get_user_from_context_name(
+        &self,
+        /// The context name to use
+        context_name: String,
+    ) -> Result<String> {
+        Ok(self
+            .kubeconfig
+            .contexts
+            .iter()
+            .find(|c| c.name == context_name)
+            .context("Given context does not exist")?
+            .context
+            .user
+            .clone())
+    }
+
+    #[roxygen]
+    /// Get the name of the cluster a context points to
+    fn get_cluster_name_from_context_name(
+        &self,
+        /// The context name to use
+        context_name: &str,
+    ) -> Result<String> {
+        Ok(self
+            .kubeconfig
+            .contexts
+            .iter()
+            .find(|c| c.name == context_name)
+            .context("Given context does not exist")?
+            .context
+            .cluster
+            .clone())
+    }
+
+    #[roxygen]
+    /// Get the cluster a context points to
+    fn get_cluster_from_context_name(
+        &self,
+        /// The context name to use
+        context_name: &str,
+    ) -> Result<&Cluster> {
+        let cluster_name = self.get_cluster_name_from_context_name(context_name)?;
+
+        Ok(&self
+            .kubeconfig
+            .clusters
+            .iter()
+            .find(|c| c.name == cluster_name)
+            .context("Context refers to a cluster that does not exist")?
+            .cluster)
+    }
+
+    #[roxygen]
+    /// Update a context token, obtained with the given method
+    fn update_token(
+        &mut self,
+        /// The context name to use
+        context_name: Option<String>,
+        /// How to obtain the new token
+        method: RefreshMethod,
+    ) -> Result<()> {
+        let context_to_update = match context_name {
+            Some(context_name) => context_name,
+            // keep scripted refreshes (e.g. `--login` with credentials from the environment) non-interactive
+            None if !std::io::stdin().is_terminal() => self.kubeconfig.current_context.clone(),
+            None => self.pick_context(
+                "Pick the context you want to refresh (type to filter)",
+                &self.get_all_contexts(),
+                "",
+            )?,
+        };
+
+        let user = self.get_user_from_context_name(context_to_update.clone())?;
+
+        let uses_token = self
+            .kubeconfig
+            .users
+            .iter()
+            .find(|u| u.name == user)
+            .context("Context refers to a user that does not exist")?
+            .user
+            .uses_token();
+
+        if !uses_token {
+            bail!("User `{user}` does not authenticate with a token (e.g. it uses a certificate or exec plugin), so it cannot be refreshed");
+        }
+
+        let token = match method {
+            RefreshMethod::Paste => OAuthToken {
+                access_token: self.prompt_token(&context_to_update)?,
+                expires_in: None,
+            },
+            RefreshMethod::Login { username } => self.login(&context_to_update, username)?,
+            RefreshMethod::Browser => self.browser_login(&context_to_update)?,
+        };
+
+        self.set_user_token(&user, token)?;
+
+        println!("{}", "Token updated succesfully!".green().bold());
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Refresh the tokens of all contexts, refreshing users that are shared between contexts only once.
+    /// Returns a summary of what happened to every context
+    fn refresh_all(
+        &mut self,
+        /// How to obtain the new tokens
+        method: RefreshMethod,
+        /// Skip contexts whose token is still accepted by the cluster
+        expired_only: bool,
+        /// Only refresh contexts whose name matches this glob
+        filter: Option<&str>,
+    ) -> Result<String> {
+        let filter = filter
+            .map(glob::Pattern::new)
+            .transpose()
+            .context("Invalid filter given")?;
+
+        let mut refreshed_users: HashMap<String, String> = HashMap::new();
+        let mut failed_users: HashMap<String, String> = HashMap::new();
+        let mut outcomes: Vec<(String, RefreshOutcome)> = Vec::new();
+
+        for context_name in self.get_all_contexts() {
+            if filter.as_ref().is_some_and(|f| !f.matches(&context_name)) {
+                continue;
+            }
+
+            let outcome = self.refresh_context(
+                &context_name,
+                method.clone(),
+                expired_only,
+                &mut refreshed_users,
+                &mut failed_users,
+            );
+            outcomes.push((context_name, outcome));
+        }
+
+        if outcomes.is_empty() {
+            bail!("No contexts match the given filter");
+        }
+
+        let width = outcomes
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let mut out = String::new();
+        for (name, outcome) in outcomes {
+            let (status, detail) = match outcome {
+                RefreshOutcome::Refreshed => ("refreshed".green(), String::new()),
+                RefreshOutcome::Skipped(reason) => ("skipped".yellow(), reason),
+                RefreshOutcome::Failed(reason) => ("failed".red(), reason),
+            };
+            out.push_str(format!("{name:width$}  {status:9}  {detail}").trim_end());
+            out.push('\n');
+        }
+
+        Ok(out)
+    }
+
+    #[roxygen]
+    /// Refresh a single context as part of [Kman::refresh_all]
+    fn refresh_context(
+        &mut self,
+        /// The context to refresh
+        context_name: &str,
+        /// How to obtain the new token
+        method: RefreshMethod,
+        /// Skip the context if its token is still accepted by the cluster
+        expired_only: bool,
+        /// Users refreshed so far, with the context they were refreshed through
+        refreshed_users: &mut HashMap<String, String>,
+        /// Users that failed to refresh so far, with the context the refresh failed for
+        failed_users: &mut HashMap<String, String>,
+    ) -> RefreshOutcome {
+        let user_name = match self.get_user_from_context_name(context_name.to_string()) {
+            Result::Ok(user_name) => user_name,
+            Err(e) => return RefreshOutcome::Failed(e.to_string()),
+        };
+        if let Some(refreshed_through) = refreshed_users.get(&user_name) {
+            return RefreshOutcome::Skipped(format!(
+                "shares user `{user_name}` with `{refreshed_through}`"
+            ));
+        }
+        // don't ask for the same user's token again after it failed
+        if let Some(failed_through) = failed_users.get(&user_name) {
+            return RefreshOutcome::Skipped(format!(
+                "shares user `{user_name}` with `{failed_through}`, which failed to refresh"
+            ));
+        }
+
+        let Some(user) = self.kubeconfig.users.iter().find(|u| u.name == user_name) else {
+            return RefreshOutcome::Failed(format!("user `{user_name}` does not exist"));
+        };
+        if !user.user.uses_token() {
+            return RefreshOutcome::Skipped("does not authenticate with a token".to_string());
+        }
+
+        if expired_only {
+            match self.check_context(context_name) {
+                Some(TokenStatus::Valid(_)) => {
+                    return RefreshOutcome::Skipped("token is still valid".to_string())
+                }
+                Some(TokenStatus::Unreachable(reason)) => {
+                    return RefreshOutcome::Skipped(format!("could not check token: {reason}"))
+                }
+                Some(TokenStatus::Expired) | None => {}
+            }
+        }
+
+        println!("\nRefreshing context {}", context_name.bold());
+        match self.update_token(Some(context_name.to_string()), method) {
+            Result::Ok(()) => {
+                refreshed_users.insert(user_name, context_name.to_string());
+                RefreshOutcome::Refreshed
+            }
+            Err(e) => {
+                failed_users.insert(user_name, context_name.to_string());
+                RefreshOutcome::Failed(format!("{e:#}"))
+            }
+        }
+    }
+
+    #[roxygen]
+    /// Store a new token on a user, and remember when it was stored and when it expires
+    fn set_user_token(
+        &mut self,
+        /// The name of the user to update
+        user_name: &str,
+        /// The new token
+        token: OAuthToken,
+    ) -> Result<()> {
+        let user = self
+            .kubeconfig
+            .users
+            .iter_mut()
+            .find(|u| u.name == user_name)
+            .context("Given user does not exist")?;
+
+        let expires_at = token.expires_in.map(|expires_in| token::now() + expires_in);
+        self.state.record_token(&token.access_token, expires_at);
+        self.state_modified = true;
+
+        user.user.token = Some(token.access_token);
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Ask the user to paste a token they requested in the console
+    fn prompt_token(
+        &self,
+        /// The context the token is for
+        context_name: &str,
+    ) -> Result<String> {
+        let cluster_name = self.get_cluster_name_from_context_name(context_name)?;
+        let token_format = self.config.token_format(&cluster_name);
+
+        let input: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Request a token ({}) in the console and paste it (or the `oc login` command) in here:",
+                token_format.hint()
+            ))
+            .interact_text()?;
+
+        let token = match login_command::parse(&input) {
+            Some(login) => {
+                if let Some(server) = &login.server {
+                    let cluster = self.get_cluster_from_context_name(context_name)?;
+                    if !login_command::same_server(server, &cluster.server) {
+                        bail!(
+                            "The login command is for {}, but context `{}` uses {}",
+                            server,
+                            context_name,
+                            cluster.server
+                        );
+                    }
+                }
+                login.token
+            }
+            None => input.trim().to_string(),
+        };
+
+        token_format.validate(&token)?;
+
+        Ok(token)
+    }
+
+    #[roxygen]
+    /// Import the token from an `oc login` command or the console's "Display Token" page,
+    /// into the context that uses the same server or into a new context
+    fn import_login(
+        &mut self,
+        /// The pasted command or page
+        input: &str,
+    ) -> Result<()> {
+        let login = login_command::parse(input)
+            .context("Could not find an `oc login` command or token in the given input")?;
+        let server = login
+            .server
+            .clone()
+            .context("Could not find which server the token is for")?;
+
+        let matching_contexts: Vec<String> = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .filter(|ctx| {
+                let same_server = self
+                    .get_cluster_from_context_name(&ctx.name)
+                    .is_ok_and(|c| login_command::same_server(&c.server, &server));
+                let uses_token = self
+                    .kubeconfig
+                    .users
+                    .iter()
+                    .find(|u| u.name == ctx.context.user)
+                    .is_some_and(|u| u.user.uses_token());
+                same_server && uses_token
+            })
+            .map(|ctx| ctx.name.clone())
+            .collect();
+
+        let context_name = match matching_contexts.len() {
+            0 => return self.import_login_as_new_context(login, server),
+            1 => matching_contexts[0].clone(),
+            _ => {
+                let selected_index = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Multiple contexts use this server, pick the one to update")
+                    .default(0)
+                    .items(&matching_contexts)
+                    .interact()?;
+                matching_contexts[selected_index].clone()
+            }
+        };
+
+        let cluster_name = self.get_cluster_name_from_context_name(&context_name)?;
+        self.config
+            .token_format(&cluster_name)
+            .validate(&login.token)?;
+
+        let user = self.get_user_from_context_name(context_name.clone())?;
+        self.set_user_token(
+            &user,
+            OAuthToken {
+                access_token: login.token,
+                expires_in: None,
+            },
+        )?;
+
+        println!(
+            "{} {}",
+            "Token updated succesfully for context".green().bold(),
+            context_name.green().bold()
+        );
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Offer to create a new cluster, user and context for a login command that matches no existing context
+    fn import_login_as_new_context(
+        &mut self,
+        /// The parsed login command
+        login: LoginCommand,
+        /// The server the login command is for
+        server: String,
+    ) -> Result<()> {
+        let create = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("No context uses {server}, do you want to add one?"))
+            .default(true)
+            .interact()?;
+        if !create {
+            bail!("No context to import the token into");
+        }
+
+        let cluster = Cluster {
+            server,
+            insecure_skip_tls_verify: login.insecure_skip_tls_verify.then_some(true),
+            ..Default::default()
+        };
+        let name: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Name for the new context")
+            .default(cluster.suggested_name())
+            .interact_text()?;
+
+        self.config.token_format(&name).validate(&login.token)?;
+        self.add_context(&name, cluster, None)?;
+        self.set_user_token(
+            &name,
+            OAuthToken {
+                access_token: login.token,
+                expires_in: None,
+            },
+        )?;
+
+        println!("Added context {}!", name.green().bold());
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Add a new context from the given values, prompting for the others when no server is given
+    fn add(
+        &mut self,
+        /// The name of the new context, cluster and user
+        name: Option<String>,
+        /// The cluster's API server URL
+        server: Option<String>,
+        /// The default namespace of the new context
+        namespace: Option<String>,
+        /// A PEM file with the cluster's certificate authority
+        certificate_authority: Option<PathBuf>,
+        /// Whether to skip verifying the server's certificate
+        insecure_skip_tls_verify: bool,
+        /// The token to log in with
+        token: Option<String>,
+    ) -> Result<()> {
+        let interactive = server.is_none();
+        let server = match server {
+            Some(server) => server,
+            None => Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("API server URL (e.g. https://api.example.com:6443)")
+                .interact_text()?,
+        };
+        let server = server.trim().trim_end_matches('/').to_string();
+        if !server.starts_with("https://") && !server.starts_with("http://") {
+            bail!("The server URL has to start with `https://` (or `http://`)");
+        }
+
+        let mut cluster = Cluster {
+            server,
+            ..Default::default()
+        };
+
+        let name = match name {
+            Some(name) => name,
+            None if interactive => Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Name for the new context")
+                .default(cluster.suggested_name())
+                .interact_text()?,
+            None => cluster.suggested_name(),
+        };
+
+        let namespace = match namespace {
+            None if interactive => {
+                let namespace: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Default namespace (leave empty for none)")
+                    .allow_empty(true)
+                    .interact_text()?;
+                Some(namespace).filter(|n| !n.is_empty())
+            }
+            namespace => namespace,
+        };
+
+        let mut certificate_authority = certificate_authority;
+        let mut insecure_skip_tls_verify = insecure_skip_tls_verify;
+        if interactive && certificate_authority.is_none() && !insecure_skip_tls_verify {
+            let choices = [
+                "Trust it through the system's certificate authorities",
+                "Trust it through a certificate authority file",
+                "Don't verify it (insecure)",
+            ];
+            match Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("How should the server's certificate be verified?")
+                .default(0)
+                .items(&choices)
+                .interact()?
+            {
+                1 => {
+                    let path: String = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Path to the PEM file")
+                        .interact_text()?;
+                    certificate_authority = Some(PathBuf::from(path));
+                }
+                2 => insecure_skip_tls_verify = true,
+                _ => {}
+            }
+        }
+
+        if let Some(path) = certificate_authority {
+            let pem = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read {}", path.display()))?;
+            if !pem.contains("-----BEGIN CERTIFICATE-----") {
+                bail!("{} does not hold a PEM encoded certificate", path.display());
+            }
+            cluster.certificate_authority_data = Some(BASE64_STANDARD.encode(pem));
+        }
+        cluster.insecure_skip_tls_verify = insecure_skip_tls_verify.then_some(true);
+
+        self.add_context(&name, cluster, namespace)?;
+
+        let token = match token {
+            Some(token) => {
+                self.config.token_format(&name).validate(&token)?;
+                Some(token)
+            }
+            None if interactive => Some(self.prompt_token(&name)?),
+            None => None,
+        };
+        if let Some(token) = token {
+            self.set_user_token(
+                &name,
+                OAuthToken {
+                    access_token: token,
+                    expires_in: None,
+                },
+            )?;
+        }
+
+        println!("Added context {}!", name.green().bold());
+        if self
+            .kubeconfig
+            .users
+            .iter()
+            .any(|u| u.name == name && u.user.token.is_none())
+        {
+            println!(
+                "Add a token with `kman refresh --name {}` before using it",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Add a new context, together with a cluster and user of the same name
+    fn add_context(
+        &mut self,
+        /// The name of the new context, cluster and user
+        name: &str,
+        /// The cluster to add
+        cluster: Cluster,
+        /// The default namespace of the new context
+        namespace: Option<String>,
+    ) -> Result<()> {
+        if self.kubeconfig.contexts.iter().any(|c| c.name == name) {
+            bail!("A context named `{name}` already exists");
+        }
+        if self.kubeconfig.clusters.iter().any(|c| c.name == name) {
+            bail!("A cluster named `{name}` already exists");
+        }
+        if self.kubeconfig.users.iter().any(|u| u.name == name) {
+            bail!("A user named `{name}` already exists");
+        }
+
+        self.kubeconfig.clusters.push(NamedCluster {
+            name: name.to_string(),
+            cluster,
+            extra: Mapping::new(),
+        });
+        self.kubeconfig.users.push(NamedUser {
+            name: name.to_string(),
+            user: User::default(),
+            extra: Mapping::new(),
+        });
+        self.kubeconfig.contexts.push(NamedContext {
+            name: name.to_string(),
+            context: ClusterContext {
+                cluster: name.to_string(),
+                user: name.to_string(),
+                namespace,
+                extra: Mapping::new(),
+            },
+            extra: Mapping::new(),
+        });
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Request a new token from the cluster's OAuth server with a username & password.
+    /// Credentials are taken from `KMAN_USERNAME`/`KMAN_PASSWORD` when set, and prompted for otherwise
+    fn login(
+        &self,
+        /// The context to log in to
+        context_name: &str,
+        /// The username to log in with
+        username: Option<String>,
+    ) -> Result<OAuthToken> {
+        let cluster = self.get_cluster_from_context_name(context_name)?;
+
+        let username = match username.or_else(|| std::env::var("KMAN_USERNAME").ok()) {
+            Some(username) => username,
+            None => Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Username")
+                .interact_text()?,
+        };
+        let password = match std::env::var("KMAN_PASSWORD") {
+            Result::Ok(password) => password,
+            Err(_) => Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Password")
+                .interact()?,
+        };
+
+        let agent = client::cluster_agent(cluster)?;
+        let metadata = oauth::discover(&agent, &cluster.server)?;
+        oauth::challenge_login(&agent, &metadata, &username, &password)
+    }
+
+    #[roxygen]
+    /// Request a new token from the cluster's OAuth server by logging in through the browser
+    fn browser_login(
+        &self,
+        /// The context to log in to
+        context_name: &str,
+    ) -> Result<OAuthToken> {
+        let cluster = self.get_cluster_from_context_name(context_name)?;
+
+        let agent = client::cluster_agent(cluster)?;
+        let metadata = oauth::discover(&agent, &cluster.server)?;
+        oauth::browser_login(&agent, &metadata)
+    }
+
+    #[roxygen]
+    /// Load the kubeconfig files from disk. Like kubectl, locations that don't exist are skipped
+    fn load_kubeconfig(
+        /// The kubeconfig file locations, in order of precedence
+        kubeconfig_locations: &[PathBuf],
+    ) -> Result<Vec<KubeConfigFile>> {
+        let mut files = Vec::new();
+
+        for location in kubeconfig_locations {
+            if !location.exists() {
+                debug!("Skipping non-existent kubeconfig {}", location.display());
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(location).with_context(|| {
+                format!("Could not read kubeconfig file {}", location.display())
+            })?;
+            let kubeconfig = Self::parse_kubeconfig(&contents, location)?;
+
+            files.push(KubeConfigFile {
+                path: location.clone(),
+                contents,
+                kubeconfig,
+            });
+        }
+
+        Ok(files)
+    }
+
+    #[roxygen]
+    /// Parse the contents of a kubeconfig file, an empty file is an empty kubeconfig
+    fn parse_kubeconfig(
+        /// The contents of the file
+        contents: &str,
+        /// The location of the file, for error messages
+        location: &Path,
+    ) -> Result<KubeConfig> {
+        if contents.trim().is_empty() {
+            return Ok(KubeConfig::default());
+        }
+
+        serde_yml::from_str(contents)
+            .with_context(|| format!("{} is not a valid Kubeconfig", location.display()))
+    }
+
+    #[roxygen]
+    /// Rename a context, user or cluster, and update every reference to it
+    fn rename(
+        &mut self,
+        /// What kind of entry to rename
+        kind: EntryKind,
+        /// The current name
+        old: &str,
+        /// The new name
+        new: &str,
+    ) -> Result<()> {
+        if new.is_empty() {
+            bail!("The new name can't be empty");
+        }
+
+        let (mut names, origins): (Vec<&mut String>, _) = match kind {
+            EntryKind::Context => (
+                self.kubeconfig
+                    .contexts
+                    .iter_mut()
+                    .map(|c| &mut c.name)
+                    .collect(),
+                &mut self.origins.contexts,
+            ),
+            EntryKind::User => (
+                self.kubeconfig
+                    .users
+                    .iter_mut()
+                    .map(|u| &mut u.name)
+                    .collect(),
+                &mut self.origins.users,
+            ),
+            EntryKind::Cluster => (
+                self.kubeconfig
+                    .clusters
+                    .iter_mut()
+                    .map(|c| &mut c.name)
+                    .collect(),
+                &mut self.origins.clusters,
+            ),
+        };
+
+        if names.iter().any(|n| *n == new) {
+            bail!("A {kind} named `{new}` already exists");
+        }
+        let name = names
+            .iter_mut()
+            .find(|n| **n == old)
+            .with_context(|| format!("Given {kind} does not exist"))?;
+        **name = new.to_string();
+
+        // keep the entry in the file and position it was loaded from
+        if let Some(origin) = origins.remove(old) {
+            origins.insert(new.to_string(), origin);
+        }
+
+        match kind {
+            EntryKind::Context => {
+                if self.kubeconfig.current_context == old {
+                    self.kubeconfig.current_context = new.to_string();
+                }
+                if self.state.rename_context(old, new) {
+                    self.state_modified = true;
+                }
+            }
+            EntryKind::User => {
+                for context in &mut self.kubeconfig.contexts {
+                    if context.context.user == old {
+                        context.context.user = new.to_string();
+                    }
+                }
+            }
+            EntryKind::Cluster => {
+                for context in &mut self.kubeconfig.contexts {
+                    if context.context.cluster == old {
+                        context.context.cluster = new.to_string();
+                    }
+                }
+                if self.config.clusters.contains_key(old) {
+                    println!(
+                        "{}",
+                        format!("Your kman config still refers to cluster `{old}`, rename it there as well").yellow()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clean up the contexts, users and clusters `oc login` created
+    fn adopt(&mut self) -> Result<()> {
+        let mut generated: Vec<String> = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .filter(|c| c.is_generated_by_oc_login())
+            .map(|c| c.name.clone())
+            .collect();
+
+        if generated.is_empty() {
+            println!("No contexts created by `oc login` found");
+            return Ok(());
+        }
+
+        // adopt the current context last, so its (most recent) token is the one that's kept
+        generated.sort_by_key(|name| *name == self.kubeconfig.current_context);
+        for context_name in &generated {
+            self.adopt_context(context_name, &generated)?;
+        }
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Adopt a single context created by `oc login`
+    fn adopt_context(
+        &mut self,
+        /// The name of the context to adopt
+        context_name: &str,
+        /// All contexts created by `oc login`, which are never adopted into
+        generated: &[String],
+    ) -> Result<()> {
+        let context = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .find(|c| c.name == context_name)
+            .context("Given context does not exist")?
+            .context
+            .clone();
+        let server = self
+            .get_cluster_from_context_name(context_name)?
+            .server
+            .clone();
+        let Some(token) = self
+            .kubeconfig
+            .users
+            .iter()
+            .find(|u| u.name == context.user)
+            .and_then(|u| u.user.token.clone())
+        else {
+            println!(
+                "{}",
+                format!("Skipping {context_name}, its user has no token").yellow()
+            );
+            return Ok(());
+        };
+
+        let existing = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .filter(|c| !generated.contains(&c.name))
+            .find(|c| {
+                let same_server = self
+                    .get_cluster_from_context_name(&c.name)
+                    .is_ok_and(|cluster| login_command::same_server(&cluster.server, &server));
+                let uses_token = self
+                    .kubeconfig
+                    .users
+                    .iter()
+                    .find(|u| u.name == c.context.user)
+                    .is_some_and(|u| u.user.uses_token());
+                same_server && uses_token
+            })
+            .map(|c| c.name.clone());
+
+        match existing {
+            Some(existing) => {
+                let user = self.get_user_from_context_name(existing.clone())?;
+                self.set_user_token(
+                    &user,
+                    OAuthToken {
+                        access_token: token,
+                        expires_in: None,
+                    },
+                )?;
+
+                self.remove_context(context_name, false)?;
+                if self.kubeconfig.current_context == context_name {
+                    self.kubeconfig.current_context = existing.clone();
+                }
+
+                println!(
+                    "Moved the token of {} to context {}",
+                    context_name,
+                    existing.green().bold()
+                );
+
+                // `oc login` puts the project it logged in to in the context's namespace
+                if let Some(namespace) = context.namespace {
+                    let existing_context = &mut self
+                        .kubeconfig
+                        .contexts
+                        .iter_mut()
+                        .find(|c| c.name == existing)
+                        .context("Given context does not exist")?
+                        .context;
+                    match &existing_context.namespace {
+                        None => {
+                            println!("Set the namespace of {existing} to {namespace}");
+                            existing_context.namespace = Some(namespace);
+                        }
+                        Some(current) if *current != namespace => println!(
+                            "{}",
+                            format!(
+                                "Kept namespace {current} of {existing} instead of {namespace}, run `kman ns {namespace} --context {existing}` to switch"
+                            )
+                            .yellow()
+                        ),
+                        Some(_) => {}
+                    }
+                }
+            }
+            None => {
+                let suggested_name = Cluster {
+                    server,
+                    ..Default::default()
+                }
+                .suggested_name();
+                let name: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!("Name for {context_name}"))
+                    .default(suggested_name)
+                    .interact_text()?;
+
+                self.rename(EntryKind::Cluster, &context.cluster, &name)?;
+                self.rename(EntryKind::User, &context.user, &name)?;
+                self.rename(EntryKind::Context, context_name, &name)?;
+                self.set_user_token(
+                    &name,
+                    OAuthToken {
+                        access_token: token,
+                        expires_in: None,
+                    },
+                )?;
+
+                println!("Adopted {} as {}", context_name, name.green().bold());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[roxygen]
+    /// Remove a user when no context refers to it anymore, returns whether it was removed
+    fn remove_user_if_orphaned(
+        &mut self,
+        /// The name of the user
+        user_name: &str,
+    ) -> bool {
+        if self
+            .kubeconfig
+            .contexts
+            .iter()
+            .any(|c| c.context.user == user_name)
+        {
+            return false;
+        }
+
+        self.kubeconfig.users.retain(|u| u.name != user_name);
+        self.origins.users.remove(user_name);
+        true
+    }
+
+    #[roxygen]
+    /// Remove a cluster when no context refers to it anymore, returns whether it was removed
+    fn remove_cluster_if_orphaned(
+        &mut self,
+        /// The name of the cluster
+        cluster_name: &str,
+    ) -> bool {
+        if self
+            .kubeconfig
+            .contexts
+            .iter()
+            .any(|c| c.context.cluster == cluster_name)
+        {
+            return false;
+        }
+
+        self.kubeconfig.clusters.retain(|c| c.name != cluster_name);
+        self.origins.clusters.remove(cluster_name);
+        true
+    }
+
+    #[roxygen]
+    /// Remove a context from the kubeconfig based on it's name
+    fn remove_context(
+        &mut self,
+        /// The name of the context to remove
+        context_name_to_remove: &str,
+        /// Whether to keep the context's user and cluster when no other context uses them
+        keep_orphans: bool,
+    ) -> Result<()> {
+        let removed = self
+            .kubeconfig
+            .contexts
+            .iter()
+            .find(|c| c.name == context_name_to_remove)
+            .context("Given context does not exist")?
+            .context
+            .clone();
+
+        self.kubeconfig
+            .contexts
+            .retain(|c| c.name != context_name_to_remove);
+        self.origins.contexts.remove(context_name_to_remove);
+
+        if !keep_orphans {
+            if self.remove_user_if_orphaned(&removed.user) {
+                println!("Removed user {}, no other context uses it", removed.user);
+            }
+            if self.remove_cluster_if_orphaned(&removed.cluster) {
+                println!(
+                    "Removed cluster {}, no other context uses it",
+                    removed.cluster
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask which context to use after the current one was removed, or unset it when none are left
+    fn replace_current_context(&mut self) -> Result<()> {
+        let contexts = self.get_all_contexts();
+        if contexts.is_empty() {
+            self.kubeconfig.current_context = String::new();
+            println!(
+                "{}",
+                "You removed the last context, no context is selected anymore".yellow()
+            );
+            return Ok(());
+        }
+
+        self.kubeconfig.current_context = self.pick_context(
+            "You removed the current context, pick the context you want to use instead",
+            &contexts,
+            "",
+        )?;
+
+        Ok(())
+    }
+
+    /// Remove all users and clusters that no context refers to, returns a description of each removed entry
+    fn prune(&mut self) -> Vec<String> {
+        let mut removed = Vec::new();
+
+        let users: Vec<String> = self
+            .kubeconfig
+            .users
+            .iter()
+            .map(|u| u.name.clone())
+            .collect();
+        for user in users {
+            if self.remove_user_if_orphaned(&user) {
+                removed.push(format!("user {user}"));
+            }
+        }
+
+        let clusters: Vec<String> = self
+            .kubeconfig
+            .clusters
+            .iter()
+            .map(|c| c.name.clone())
+            .collect();
+        for cluster in clusters {
+            if self.remove_cluster_if_orphaned(&cluster) {
+                removed.push(format!("cluster {cluster}"));
+            }
+        }
+
+        removed
+    }
+}
+
+/// Whether all characters of `needle` appear in `haystack`, in the same order
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+/// The number of characters that have to be inserted, removed or replaced to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Whether a name can be used for a namespace, which has to be a DNS label (RFC 1123)
+fn is_valid_namespace(name: &str) -> bool {
+    name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+fn main() -> Result<()> {
+    setup_panic!(
+        Metadata::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+            .authors("Stef Robbe <stef.robbe@protonmail.com>")
+            .homepage("https://github.com/stef16robbe/kman")
+            .support("- Open an issue on the Github repo")
+    );
+
+    let cli = Cli::parse();
+    env_logger::Builder::new()
+        .filter_level(cli.verbose.log_level_filter())
+        .init();
+
+    // TODO: remove `unwrap()`
+    let base_dirs = BaseDirs::new().unwrap();
+    // `KUBECONFIG` may hold multiple paths (`a:b:c`), just like kubectl
+    let mut kubeconfig_locations: Vec<PathBuf> = Vec::new();
+    if let Some(kubeconfig_env) = std::env::var_os("KUBECONFIG") {
+        for location in std::env::split_paths(&kubeconfig_env) {
+            if !location.as_os_str().is_empty() && !kubeconfig_locations.contains(&location) {
+                kubeconfig_locations.push(location);
+            }
+        }
+    }
+    if kubeconfig_locations.is_empty() {
+        kubeconfig_locations.push(base_dirs.home_dir().join(Path::new(".kube/config")));
+    }
+
+    // backups don't need the kubeconfig, which may well be broken when they're needed
+    match cli.command {
+        Some(Commands::Backups { command }) => {
+            match command {
+                BackupsCommands::List {} => print!("{}", Kman::list_backups()?),
+                BackupsCommands::Diff { id } => print!("{}", Kman::diff_backup(&id)?),
+            }
+            return Ok(());
+        }
+        Some(Commands::Undo { id }) => return Kman::undo(id, cli.dry_run, Config::load()?.backups),
+        _ => {}
+    }
+
+    let mut files = Kman::load_kubeconfig(&kubeconfig_locations)?;
+    // like kubectl, commands that add a context start a new kubeconfig in the first location
+    if files.is_empty()
+        && matches!(
+            cli.command,
+            Some(Commands::Add { .. } | Commands::ImportLogin { .. })
+        )
+    {
+        println!(
+            "No kubeconfig found, creating {}",
+            kubeconfig_locations[0].display()
+        );
+        files.push(KubeConfigFile {
+            path: kubeconfig_locations[0].clone(),
+            contents: String::new(),
+            kubeconfig: KubeConfig::default(),
+        });
+    }
+    if files.is_empty() {
+        let locations: Vec<String> = kubeconfig_locations
+            .iter()
+            .map(|l| l.display().to_string())
+            .collect();
+        bail!(
+            "No file found at: {}\nYou can specify a custom location with the `KUBECONFIG` environment variable",
+            locations.join(", ")
+        );
+    }
+
+    let mut kman = Kman::new(files, State::load()?, Config::load()?);
+
+    if let Some(command) = cli.command {
+        match command {
+            Commands::List { no_check } => {
+                let contexts = kman.list_contexts(!no_check)?;
+                println!(
+                    "{}\n\n{}",
+                    "Here are your current contexts:".bold(),
+                    contexts
+                );
+            }
+            Commands::Select { name, no_check } => {
+                let context_to_select = if let Some(name) = name {
+                    if name == "-" {
+                        kman.previous_context()?
+                    } else {
+                        kman.find_context(&name, std::io::stdin().is_terminal())?
+                    }
+                } else {
+                    kman.pick_context(
+                        "Pick the context you want to use (type to filter)",
+                        &kman.get_all_contexts(),
+                        "",
+                    )?
+                };
+
+                kman.select_context(context_to_select, !no_check)?;
+            }
+            Commands::Ns { name, context } => kman.set_namespace(context, name)?,
+            Commands::History { count } => print!("{}", kman.history(count)?),
+            Commands::Status {} => {
+                let statuses = kman.context_statuses()?;
+                println!("{}\n\n{}", "Token status per context:".bold(), statuses);
+            }
+            Commands::ImportLogin { command } => {
+                let input = if command.is_empty() {
+                    if std::io::stdin().is_terminal() {
+                        println!("Paste the `oc login` command or the \"Display Token\" page, then press Ctrl-D:");
+                    }
+                    let mut input = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut input)
+                        .context("Could not read from stdin")?;
+                    input
+                } else {
+                    let command = shell_words::join(&command);
+                    if command.starts_with("oc login") {
+                        command
+                    } else {
+                        format!("oc login {command}")
+                    }
+                };
+
+                kman.import_login(&input)?;
+            }
+            Commands::Rename {
+                old,
+                new,
+                user,
+                cluster,
+            } => {
+                let kind = if user {
+                    EntryKind::User
+                } else if cluster {
+                    EntryKind::Cluster
+                } else {
+                    EntryKind::Context
+                };
+                kman.rename(kind, &old, &new)?;
+                println!("Renamed {kind} {} to {}!", old, new.green().bold());
+            }
+            Commands::Adopt {} => kman.adopt()?,
+            Commands::Backups { .. } | Commands::Undo { .. } => {
+                unreachable!("handled before the kubeconfig is loaded")
+            }
+            Commands::Cluster { command } => match command {
+                ClusterCommands::Show { name } => print!("{}", kman.show_cluster(&name)?),
+            },
+            Commands::Refresh {
+                name,
+                all,
+                expired_only,
+                filter,
+                login,
+                username,
+                browser,
+            } => {
+                let method = if login {
+                    RefreshMethod::Login { username }
+                } else if browser {
+                    RefreshMethod::Browser
+                } else {
+                    RefreshMethod::Paste
+                };
+                if all {
+                    let summary = kman.refresh_all(method, expired_only, filter.as_deref())?;
+                    println!("\n{}\n\n{}", "Refresh summary:".bold(), summary);
+                } else {
+                    kman.update_token(name, method)?
+                }
+            }
+            Commands::Add {
+                name,
+                server,
+                namespace,
+                certificate_authority,
+                insecure_skip_tls_verify,
+                token,
+            } => kman.add(
+                name,
+                server,
+                namespace,
+                certificate_authority,
+                insecure_skip_tls_verify,
+                token,
+            )?,
+            Commands::Remove { keep_orphans } => {
+                let mut contexts = kman.get_all_contexts();
+                let mut contexts_to_remove = Vec::new();
+                loop {
+                    let context = kman.pick_context(
+                        "Pick the context you want to remove (type to filter)",
+                        &contexts,
+                        "",
+                    )?;
+                    contexts.retain(|c| *c != context);
+                    contexts_to_remove.push(context);
+
+                    if contexts.is_empty()
+                        || !Confirm::with_theme(&ColorfulTheme::default())
+                            .with_prompt("Remove another context as well?")
+                            .default(false)
+                            .interact()?
+                    {
+                        break;
+                    }
+                }
+
+                let current_context = kman.kubeconfig.current_context.clone();
+                for context_to_remove in &contexts_to_remove {
+                    kman.remove_context(context_to_remove, keep_orphans)?;
+                    println!("Removed context {}!", context_to_remove);
+                }
+
+                if contexts_to_remove.contains(&current_context) {
+                    kman.replace_current_context()?;
+                }
+            }
+            Commands::Prune {} => {
+                let removed = kman.prune();
+                if removed.is_empty() {
+                    println!("Every user and cluster is used by a context, nothing to prune");
+                }
+                for entry in removed {
+                    println!("Removed {}!", entry);
+                }
+            }
+        }
+
+        // the subcommand's name, without any arguments that could hold a token
+        let command = std::env::args()
+            .skip(1)
+            .find(|arg| !arg.starts_with('-'))
+            .unwrap_or_default();
+        if cli.dry_run {
+            print!("{}", kman.describe_changes()?);
+        } else {
+            if kman.is_modified() {
+                kman.update_kubeconfig(&command)?;
+            }
+            // only once the kubeconfig is written, so the state never mentions a token that wasn't stored
+            if kman.state_modified {
+                kman.state.save()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A kubeconfig the way `aws eks update-kubeconfig` and kubectl write it
+    const EKS_KUBECONFIG: &str = r#"# written by aws eks update-kubeconfig
+apiVersion: v1
+clusters:
+- cluster:
+    certificate-authority-data: LS0tLS1CRUdJTg==
+    server: https://ABCDEF.gr7.eu-west-1.eks.amazonaws.com
+  name: arn:aws:eks:eu-west-1:123456789012:cluster/dev
+- cluster:
+    server: https://api.prod.example.com:6443
+  name: prod
+contexts:
+- context:
+    cluster: arn:aws:eks:eu-west-1:123456789012:cluster/dev
+    user: arn:aws:eks:eu-west-1:123456789012:cluster/dev
+  name: dev
+- context:
+    cluster: prod
+    namespace: web
+    user: prod
+  name: prod
+current-context: prod
+kind: Config
+preferences: {}
+users:
+- name: arn:aws:eks:eu-west-1:123456789012:cluster/dev
+  user:
+    exec:
+      apiVersion: client.authentication.k8s.io/v1beta1
+      args:
+      - --region
+      - eu-west-1
+      - eks
+      - get-token
+      - --cluster-name
+      - dev
+      command: aws
+      env: null
+      interactiveMode: IfAvailable
+      provideClusterInfo: false
+- name: prod
+  user:
+    token: sha256~old
+"#;
+
+    /// A kman for kubeconfig files with the given contents, in `KUBECONFIG` order
+    fn kman(contents: &[&str]) -> Kman {
+        let files = contents
+            .iter()
+            .enumerate()
+            .map(|(i, contents)| {
+                let path = PathBuf::from(format!("config-{i}"));
+                KubeConfigFile {
+                    kubeconfig: Kman::parse_kubeconfig(contents, &path).unwrap(),
+                    path,
+                    contents: contents.to_string(),
+                }
+            })
+            .collect();
+        Kman::new(files, State::default(), Config::default())
+    }
+
+    /// The text kman would write to each of its kubeconfig files
+    fn rendered(kman: &Kman) -> Vec<String> {
+        let originals: Vec<KubeConfig> = kman.files.iter().map(|f| f.kubeconfig.clone()).collect();
+        kman.files
+            .iter()
+            .zip(kman.kubeconfig.split(&originals, &kman.origins))
+            .map(|(file, kubeconfig)| {
+                let (yaml, formatting_lost) =
+                    Kman::render_kubeconfig(&file.contents, &file.kubeconfig, &kubeconfig).unwrap();
+                assert!(
+                    !formatting_lost,
+                    "{} is rewritten as a whole",
+                    file.path.display()
+                );
+                yaml
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_kubectl_written_files_in_place() {
+        let mut kman = kman(&[EKS_KUBECONFIG]);
+        kman.kubeconfig.users[1].user.token = Some("sha256~new".to_string());
+
+        assert_eq!(
+            rendered(&kman),
+            [EKS_KUBECONFIG.replace("sha256~old", "sha256~new")]
+        );
+    }
+
+    #[test]
+    fn selecting_a_context_changes_a_single_line() {
+        // no apiVersion, kind or preferences, which kman's model fills in
+        let text = EKS_KUBECONFIG
+            .replace("apiVersion: v1\n", "")
+            .replace("kind: Config\npreferences: {}\n", "");
+        let mut kman = kman(&[&text]);
+        kman.select_context("dev".to_string(), false).unwrap();
+
+        let rendered = rendered(&kman).remove(0);
+        let changed: Vec<(&str, &str)> = text
+            .lines()
+            .zip(rendered.lines())
+            .filter(|(old, new)| old != new)
+            .collect();
+        assert_eq!(text.lines().count(), rendered.lines().count());
+        assert_eq!(changed, [("current-context: prod", "current-context: dev")]);
+    }
+
+    #[test]
+    fn renaming_a_context_renames_it_in_the_history() {
+        let contexts = "contexts:\n- context:\n    cluster: a\n    user: a\n  name: a\n- context:\n    cluster: b\n    user: b\n  name: b\ncurrent-context: a\n";
+        let mut kman = kman(&[contexts]);
+        kman.select_context("b".to_string(), false).unwrap();
+        kman.select_context("a".to_string(), false).unwrap();
+        kman.state_modified = false;
+
+        kman.rename(EntryKind::Context, "b", "c").unwrap();
+        assert!(kman.state_modified);
+        assert_eq!(kman.previous_context().unwrap(), "c");
+        let history: Vec<&str> = kman
+            .state
+            .history
+            .iter()
+            .map(|s| s.context.as_str())
+            .collect();
+        assert_eq!(history, ["c", "a"]);
+    }
+
+    /// A kman with a context for every name
+    fn with_contexts(names: &[&str]) -> Kman {
+        let mut yaml = String::from("contexts:\n");
+        for name in names {
+            yaml.push_str(&format!(
+                "- context:\n    cluster: {name}\n    user: {name}\n  name: {name}\n"
+            ));
+        }
+        kman(&[&yaml])
+    }
+
+    #[test]
+    fn finds_exact_names_before_prefixes() {
+        let kman = with_contexts(&["prod", "prod-eu", "production"]);
+        assert_eq!(kman.find_context("prod", false).unwrap(), "prod");
+        assert_eq!(kman.find_context("prod-", false).unwrap(), "prod-eu");
+        assert_eq!(kman.find_context("PRODU", false).unwrap(), "production");
+    }
+
+    #[test]
+    fn finds_unique_substrings_and_subsequences() {
+        let kman = with_contexts(&["staging-eu", "staging-us", "prod-us"]);
+        assert_eq!(kman.find_context("ing-e", false).unwrap(), "staging-eu");
+        assert_eq!(kman.find_context("stgus", false).unwrap(), "staging-us");
+        assert_eq!(kman.find_context("pus", false).unwrap(), "prod-us");
+    }
+
+    #[test]
+    fn lists_the_matches_when_ambiguous_without_a_terminal() {
+        let kman = with_contexts(&["staging-eu", "staging-us", "prod-us"]);
+        let error = kman.find_context("staging", false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`staging` matches multiple contexts: staging-eu, staging-us"
+        );
+        // a prefix match wins over a substring match
+        let error = kman.find_context("us", false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`us` matches multiple contexts: staging-us, prod-us"
+        );
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let kman = with_contexts(&["staging", "production"]);
+        assert_eq!(
+            kman.find_context("stagnig", false).unwrap_err().to_string(),
+            "Given context does not exist, did you mean `staging`?"
+        );
+        // up to a third of the typed characters may differ, and at least 2
+        assert_eq!(
+            kman.find_context("prodxxtion", false)
+                .unwrap_err()
+                .to_string(),
+            "Given context does not exist, did you mean `production`?"
+        );
+        assert_eq!(
+            kman.find_context("proxxxxtion", false)
+                .unwrap_err()
+                .to_string(),
+            "Given context does not exist"
+        );
+        assert_eq!(
+            kman.find_context("dev", false).unwrap_err().to_string(),
+            "Given context does not exist"
+        );
+    }
+
+    #[test]
+    fn compares_strings() {
+        assert!(is_subsequence("stgus", "staging-us"));
+        assert!(is_subsequence("", "prod"));
+        assert!(!is_subsequence("sut", "staging-us"));
+
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("staging", "staging"), 0);
+        assert_eq!(edit_distance("stagnig", "staging"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn edits_partial_files_in_place() {
+        let clusters = "# shared clusters\nclusters:\n- cluster:\n    server: https://api.dev.example.com:6443 # dev\n  name: dev\n";
+        let mut kman = kman(&[clusters]);
+        kman.kubeconfig.clusters[0].cluster.server =
+            "https://api.test.example.com:6443".to_string();
+
+        assert_eq!(rendered(&kman), [clusters.replace("api.dev.", "api.test.")]);
+    }
+
+    #[test]
+    fn renames_entries_in_the_file_they_came_from() {
+        let contexts = "contexts:\n- context:\n    cluster: dev\n    user: dev\n  name: dev\ncurrent-context: dev\n";
+        let users = "# users\nusers:\n- name: dev # the dev user\n  user:\n    token: sha256~dev\n";
+        let mut kman = kman(&[contexts, users]);
+        kman.rename(EntryKind::User, "dev", "developer").unwrap();
+
+        assert_eq!(
+            rendered(&kman),
+            [
+                contexts.replace("user: dev", "user: developer"),
+                users.replace("name: dev", "name: developer")
+            ]
+        );
+    }
 }
@@ -0,0 +1,405 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::prelude::*;
+use roxygen::roxygen;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+use ureq::Agent;
+
+/// The OAuth client OpenShift provides for clients that answer authentication challenges (e.g. `oc login -u`)
+const CHALLENGING_CLIENT_ID: &str = "openshift-challenging-client";
+
+/// The OAuth client OpenShift provides for CLIs that log in through a browser (e.g. `oc login --web`)
+const CLI_CLIENT_ID: &str = "openshift-cli-client";
+
+/// How long to wait for the user to finish logging in through their browser
+const BROWSER_LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The page shown in the browser once kman received the authorization code
+const CALLBACK_PAGE: &str = "<html><body><p>kman received your login, you can close this window and return to your terminal.</p></body></html>";
+
+/// OAuth server metadata as served on `/.well-known/oauth-authorization-server`
+#[derive(Debug, Deserialize)]
+pub struct OAuthMetadata {
+    /// Where to request authorization
+    pub authorization_endpoint: String,
+    /// Where to exchange authorization codes for tokens
+    pub token_endpoint: String,
+}
+
+/// An access token handed out by the OAuth server
+#[derive(Debug, Deserialize)]
+pub struct OAuthToken {
+    /// The bearer token itself
+    pub access_token: String,
+    /// How many seconds the token is valid for, if the server told us
+    pub expires_in: Option<u64>,
+}
+
+#[roxygen]
+/// Discover the OAuth server that belongs to a cluster
+pub fn discover(
+    /// An agent configured for the cluster
+    agent: &Agent,
+    /// The cluster's API server URL
+    server: &str,
+) -> Result<OAuthMetadata> {
+    let url = format!(
+        "{}/.well-known/oauth-authorization-server",
+        server.trim_end_matches('/')
+    );
+    let mut response = agent
+        .get(&url)
+        .call()
+        .with_context(|| format!("Could not reach {url}"))?;
+
+    if !response.status().is_success() {
+        bail!(
+            "Cluster does not expose an OAuth server (`{}` returned {})",
+            url,
+            response.status()
+        );
+    }
+
+    response
+        .body_mut()
+        .read_json()
+        .context("Cluster returned invalid OAuth server metadata")
+}
+
+#[roxygen]
+/// Request a token by answering the OAuth server's basic auth challenge, like `oc login -u <username>` does
+pub fn challenge_login(
+    /// An agent configured for the cluster
+    agent: &Agent,
+    /// The cluster's OAuth server
+    metadata: &OAuthMetadata,
+    /// The username to log in with
+    username: &str,
+    /// The password to log in with
+    password: &str,
+) -> Result<OAuthToken> {
+    let credentials = BASE64_STANDARD.encode(format!("{username}:{password}"));
+    let response = agent
+        .get(&metadata.authorization_endpoint)
+        .query("response_type", "token")
+        .query("client_id", CHALLENGING_CLIENT_ID)
+        // OpenShift refuses challenges without a CSRF header
+        .header("X-CSRF-Token", "1")
+        .header("Authorization", format!("Basic {credentials}"))
+        .call()
+        .context("Could not reach the OAuth server")?;
+
+    let status = response.status();
+    if status.as_u16() == 401 {
+        bail!("Login failed, check your username and password");
+    }
+    if !status.is_redirection() {
+        bail!("OAuth server did not hand out a token (got {status})");
+    }
+
+    let location = response
+        .headers()
+        .get("Location")
+        .and_then(|l| l.to_str().ok())
+        .context("OAuth server redirected without a location")?;
+    // the token is returned in the fragment, errors are returned in the query
+    let fragment = location
+        .split_once('#')
+        .or_else(|| location.split_once('?'))
+        .map(|(_, fragment)| fragment)
+        .context("OAuth server did not return a token")?;
+
+    let mut access_token = None;
+    let mut expires_in = None;
+    for (key, value) in decode_query(fragment) {
+        match key.as_str() {
+            "access_token" => access_token = Some(value),
+            "expires_in" => expires_in = value.parse().ok(),
+            "error" => bail!("OAuth server returned an error: {value}"),
+            _ => {}
+        }
+    }
+
+    Ok(OAuthToken {
+        access_token: access_token.context("OAuth server did not return a token")?,
+        expires_in,
+    })
+}
+
+#[roxygen]
+/// Request a token by logging in through the browser, like `oc login --web` does.
+/// A loopback listener receives the authorization code, which is exchanged for a token using PKCE
+pub fn browser_login(
+    /// An agent configured for the cluster
+    agent: &Agent,
+    /// The cluster's OAuth server
+    metadata: &OAuthMetadata,
+) -> Result<OAuthToken> {
+    let server = Server::http("127.0.0.1:0")
+        .map_err(|e| anyhow!("Could not start the login callback listener: {e}"))?;
+    let port = server
+        .server_addr()
+        .to_ip()
+        .context("Login callback listener has no address")?
+        .port();
+    let redirect_uri = format!("http://127.0.0.1:{port}/callback");
+
+    let code_verifier = random_string()?;
+    let code_challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+    let state = random_string()?;
+
+    let authorize_url = format!(
+        "{}?{}",
+        metadata.authorization_endpoint,
+        encode_query(&[
+            ("response_type", "code"),
+            ("client_id", CLI_CLIENT_ID),
+            ("redirect_uri", &redirect_uri),
+            ("code_challenge", &code_challenge),
+            ("code_challenge_method", "S256"),
+            ("state", &state),
+        ])
+    );
+
+    println!("Opening your browser to log in, if it doesn't open visit:\n{authorize_url}");
+    if let Err(e) = open::that(&authorize_url) {
+        log::warn!("Could not open browser: {e}");
+    }
+
+    let code = loop {
+        let request = server
+            .recv_timeout(BROWSER_LOGIN_TIMEOUT)
+            .context("Login callback listener failed")?
+            .context("Timed out waiting for the browser login to finish")?;
+
+        let Some(query) = request.url().strip_prefix("/callback?") else {
+            // e.g. the browser requesting a favicon
+            let _ = request.respond(Response::empty(404));
+            continue;
+        };
+        let params = decode_query(query);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let html = Header::from_bytes("Content-Type", "text/html").expect("valid header");
+        let _ = request.respond(Response::from_string(CALLBACK_PAGE).with_header(html));
+
+        if let Some(error) = param("error") {
+            bail!("Login failed: {error}");
+        }
+        if param("state").as_deref() != Some(state.as_str()) {
+            bail!("Login callback state does not match, refusing to continue");
+        }
+        break param("code").context("Login callback did not include an authorization code")?;
+    };
+
+    let mut response = agent
+        .post(&metadata.token_endpoint)
+        .send_form([
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("client_id", CLI_CLIENT_ID),
+            ("code_verifier", code_verifier.as_str()),
+        ])
+        .context("Could not reach the OAuth server")?;
+
+    if !response.status().is_success() {
+        let body = response.body_mut().read_to_string().unwrap_or_default();
+        bail!(
+            "OAuth server refused the authorization code ({}): {}",
+            response.status(),
+            body
+        );
+    }
+
+    response
+        .body_mut()
+        .read_json()
+        .context("OAuth server returned an invalid token response")
+}
+
+/// Generate a random, URL-safe string for use as PKCE verifier or state
+fn random_string() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| anyhow!("Could not generate random data: {e}"))?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Percent-encode query parameters
+fn encode_query(params: &[(&str, &str)]) -> String {
+    let encode = |value: &str| {
+        value
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Decode percent-encoded query parameters
+fn decode_query(query: &str) -> Vec<(String, String)> {
+    let decode = |value: &str| {
+        let bytes = value.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'+' => decoded.push(b' '),
+                b'%' => match bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                },
+                b => decoded.push(b),
+            }
+            i += 1;
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    };
+
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (decode(key), decode(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client,
+        kubeconfig::Cluster,
+        mock_server::{self, MockServer},
+    };
+    use tiny_http::Header;
+
+    /// An agent configured the way kman talks to clusters
+    fn agent(server: &MockServer) -> Agent {
+        client::cluster_agent(&Cluster {
+            server: server.url.clone(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// Metadata pointing at the mock server's authorize endpoint
+    fn metadata(server: &MockServer) -> OAuthMetadata {
+        OAuthMetadata {
+            authorization_endpoint: format!("{}/oauth/authorize", server.url),
+            token_endpoint: format!("{}/oauth/token", server.url),
+        }
+    }
+
+    /// A redirect to the given location
+    fn redirect(location: &str) -> mock_server::MockResponse {
+        mock_server::status(302).with_header(Header::from_bytes("Location", location).unwrap())
+    }
+
+    #[test]
+    fn discovers_the_oauth_server() {
+        let server = MockServer::start(|request| match request.url() {
+            "/.well-known/oauth-authorization-server" => mock_server::json(
+                200,
+                serde_json::json!({
+                    "issuer": "https://oauth.example.com",
+                    "authorization_endpoint": "https://oauth.example.com/oauth/authorize",
+                    "token_endpoint": "https://oauth.example.com/oauth/token",
+                }),
+            ),
+            _ => mock_server::status(404),
+        });
+
+        let metadata = discover(&agent(&server), &format!("{}/", server.url)).unwrap();
+        assert_eq!(
+            metadata.authorization_endpoint,
+            "https://oauth.example.com/oauth/authorize"
+        );
+        assert_eq!(
+            metadata.token_endpoint,
+            "https://oauth.example.com/oauth/token"
+        );
+        assert_eq!(
+            server.requests(),
+            ["GET /.well-known/oauth-authorization-server"]
+        );
+    }
+
+    #[test]
+    fn discovery_fails_without_an_oauth_server() {
+        let server = MockServer::start(|_| mock_server::status(404));
+
+        let error = discover(&agent(&server), &server.url).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("does not expose an OAuth server"));
+    }
+
+    #[test]
+    fn challenge_login_reads_the_token_from_the_fragment() {
+        let server = MockServer::start(|request| {
+            let credentials = BASE64_STANDARD.encode("developer:secret");
+            let authorized = mock_server::header(request, "Authorization")
+                == Some(format!("Basic {credentials}"))
+                && mock_server::header(request, "X-CSRF-Token").is_some()
+                && request
+                    .url()
+                    .contains("client_id=openshift-challenging-client");
+            if authorized {
+                redirect("https://oauth.example.com/oauth/token/implicit#access_token=sha256~abc%7Edef&expires_in=86400&token_type=Bearer")
+            } else {
+                mock_server::status(401)
+            }
+        });
+
+        let token =
+            challenge_login(&agent(&server), &metadata(&server), "developer", "secret").unwrap();
+        assert_eq!(token.access_token, "sha256~abc~def");
+        assert_eq!(token.expires_in, Some(86_400));
+    }
+
+    #[test]
+    fn challenge_login_rejects_wrong_credentials() {
+        let server = MockServer::start(|_| mock_server::status(401));
+
+        let error =
+            challenge_login(&agent(&server), &metadata(&server), "developer", "wrong").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("check your username and password"));
+    }
+
+    #[test]
+    fn challenge_login_reports_oauth_errors() {
+        let server = MockServer::start(|_| {
+            redirect("https://oauth.example.com/oauth/token/implicit#error=access_denied&error_description=denied")
+        });
+
+        let error = challenge_login(&agent(&server), &metadata(&server), "developer", "secret")
+            .unwrap_err();
+        assert!(error.to_string().contains("access_denied"));
+    }
+}
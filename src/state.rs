@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use roxygen::roxygen;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{atomic, token};
+
+/// How long kman keeps metadata on a token it stored, in seconds
+const TOKEN_METADATA_RETENTION: u64 = 90 * 86_400;
+
+/// How many context selections kman remembers
+const HISTORY_LENGTH: usize = 50;
+
+/// State kman keeps for itself in its data directory, next to the kubeconfig
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Metadata on the tokens kman wrote, keyed by the token's SHA-256 hash so no secrets end up in here
+    #[serde(default)]
+    pub tokens: BTreeMap<String, TokenMetadata>,
+    /// The context that was active before the last one selected with `kman select`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_context: Option<String>,
+    /// The contexts selected with `kman select`, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Selection>,
+}
+
+/// A context that was selected with `kman select`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Selection {
+    /// The selected context's name
+    pub context: String,
+    /// When the context was selected (unix timestamp)
+    pub selected_at: u64,
+}
+
+/// What kman knows about a token it stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    /// When kman stored the token (unix timestamp)
+    pub issued_at: u64,
+    /// When the token expires (unix timestamp), if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// When a token stops working, as far as kman can tell without asking the cluster
+#[derive(Debug, Clone, Copy)]
+pub enum TokenExpiry {
+    /// The token expires at this time (unix timestamp)
+    ExpiresAt(u64),
+    /// Only the time the token was issued at is known (unix timestamp)
+    IssuedAt(u64),
+}
+
+impl State {
+    /// The directory kman keeps its own files in. Can be overridden with `KMAN_DATA_DIR`
+    pub fn data_dir() -> Result<PathBuf> {
+        if let Some(data_dir) = std::env::var_os("KMAN_DATA_DIR") {
+            return Ok(data_dir.into());
+        }
+
+        Ok(ProjectDirs::from("", "", "kman")
+            .context("Could not determine your home directory")?
+            .data_dir()
+            .to_path_buf())
+    }
+
+    /// Load kman's state from disk, starting fresh when there is none yet
+    pub fn load() -> Result<State> {
+        let location = Self::data_dir()?.join("state.yaml");
+        if !location.exists() {
+            return Ok(State::default());
+        }
+
+        let state_str = std::fs::read_to_string(&location)
+            .with_context(|| format!("Could not read {}", location.display()))?;
+        serde_yml::from_str(&state_str)
+            .with_context(|| format!("{} is not a valid kman state file", location.display()))
+    }
+
+    /// Save kman's state to disk
+    pub fn save(&self) -> Result<()> {
+        let data_dir = Self::data_dir()?;
+        std::fs::create_dir_all(&data_dir)
+            .with_context(|| format!("Could not create {}", data_dir.display()))?;
+
+        let location = data_dir.join("state.yaml");
+        let yaml = serde_yml::to_string(self).context("Could not serialize kman state")?;
+        atomic::write(&location, yaml.as_bytes())
+    }
+
+    #[roxygen]
+    /// Remember that a token was stored just now
+    pub fn record_token(
+        &mut self,
+        /// The token that was stored
+        token: &str,
+        /// When the token expires (unix timestamp), if known
+        expires_at: Option<u64>,
+    ) {
+        let now = token::now();
+        self.tokens.retain(|_, metadata| {
+            now.saturating_sub(metadata.issued_at) < TOKEN_METADATA_RETENTION
+        });
+        self.tokens.insert(
+            Self::token_key(token),
+            TokenMetadata {
+                issued_at: now,
+                expires_at,
+            },
+        );
+    }
+
+    #[roxygen]
+    /// Remember that a context was selected just now
+    pub fn record_selection(
+        &mut self,
+        /// The context that was active before
+        previous: &str,
+        /// The context that was selected
+        context: &str,
+    ) {
+        if !previous.is_empty() && previous != context {
+            self.previous_context = Some(previous.to_string());
+        }
+
+        self.history.push(Selection {
+            context: context.to_string(),
+            selected_at: token::now(),
+        });
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.drain(..self.history.len() - HISTORY_LENGTH);
+        }
+    }
+
+    #[roxygen]
+    /// Follow the rename of a context, returns whether kman remembered anything about it
+    pub fn rename_context(
+        &mut self,
+        /// The context's old name
+        old: &str,
+        /// The context's new name
+        new: &str,
+    ) -> bool {
+        let mut renamed = false;
+        if self.previous_context.as_deref() == Some(old) {
+            self.previous_context = Some(new.to_string());
+            renamed = true;
+        }
+        for selection in self.history.iter_mut().filter(|s| s.context == old) {
+            selection.context = new.to_string();
+            renamed = true;
+        }
+
+        renamed
+    }
+
+    #[roxygen]
+    /// Work out when a token stops working, from its claims if it is a JWT and from kman's own records otherwise
+    pub fn token_expiry(
+        &self,
+        /// The token to look up
+        token: &str,
+    ) -> Option<TokenExpiry> {
+        let recorded = self.tokens.get(&Self::token_key(token));
+        let claims = token::decode_jwt(token).unwrap_or_default();
+
+        if let Some(expires_at) = claims
+            .expires_at
+            .or_else(|| recorded.and_then(|m| m.expires_at))
+        {
+            return Some(TokenExpiry::ExpiresAt(expires_at));
+        }
+
+        recorded
+            .map(|m| m.issued_at)
+            .or(claims.issued_at)
+            .map(TokenExpiry::IssuedAt)
+    }
+
+    /// The key a token's metadata is stored under
+    fn token_key(token: &str) -> String {
+        Sha256::digest(token.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
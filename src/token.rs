@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use base64::prelude::*;
+use regex::Regex;
+use roxygen::roxygen;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The claims kman cares about in a JWT bearer token
+#[derive(Debug, Default)]
+pub struct JwtClaims {
+    /// When the token was issued (unix timestamp)
+    pub issued_at: Option<u64>,
+    /// When the token expires (unix timestamp)
+    pub expires_at: Option<u64>,
+}
+
+#[roxygen]
+/// Decode the claims of a JWT bearer token, without verifying its signature.
+/// Returns `None` for opaque tokens such as OpenShift's `sha256~` tokens
+pub fn decode_jwt(
+    /// The bearer token
+    token: &str,
+) -> Option<JwtClaims> {
+    let mut parts = token.split('.');
+    let (_header, payload, _signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let payload = BASE64_URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    Some(JwtClaims {
+        issued_at: claims.get("iat").and_then(|v| v.as_u64()),
+        expires_at: claims.get("exp").and_then(|v| v.as_u64()),
+    })
+}
+
+/// The current time as a unix timestamp
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[roxygen]
+/// Format a number of seconds the way humans talk about it, e.g. `3h` or `2d`
+pub fn format_duration(
+    /// The duration in seconds
+    seconds: u64,
+) -> String {
+    match seconds {
+        s if s >= 86_400 => format!("{}d", s / 86_400),
+        s if s >= 3_600 => format!("{}h", s / 3_600),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
+/// The formats kman knows tokens to come in
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TokenPreset {
+    /// OpenShift's `sha256~` prefixed tokens
+    #[default]
+    OpenshiftSha256,
+    /// OpenShift tokens from before the `sha256~` prefix was introduced
+    OpenshiftLegacy,
+    /// JSON Web Tokens, e.g. Kubernetes service account tokens
+    Jwt,
+    /// Rancher API tokens
+    Rancher,
+    /// Anything, as long as it doesn't contain whitespace
+    Any,
+}
+
+impl TokenPreset {
+    /// All presets, with the name they are configured by
+    const ALL: [(&'static str, TokenPreset); 5] = [
+        ("openshift-sha256", TokenPreset::OpenshiftSha256),
+        ("openshift-legacy", TokenPreset::OpenshiftLegacy),
+        ("jwt", TokenPreset::Jwt),
+        ("rancher", TokenPreset::Rancher),
+        ("any", TokenPreset::Any),
+    ];
+}
+
+/// A token format as written in the config file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTokenFormat {
+    /// The name of a preset
+    Preset(String),
+    /// A custom format
+    Custom {
+        /// The regex a token has to match
+        regex: String,
+    },
+}
+
+/// The format a token is expected to have: a named preset or a custom regex
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawTokenFormat")]
+pub enum TokenFormat {
+    /// One of the formats kman knows
+    Preset(TokenPreset),
+    /// A custom format
+    Custom {
+        /// The regex a token has to match
+        regex: String,
+    },
+}
+
+impl TryFrom<RawTokenFormat> for TokenFormat {
+    type Error = String;
+
+    fn try_from(raw: RawTokenFormat) -> std::result::Result<Self, Self::Error> {
+        match raw {
+            RawTokenFormat::Preset(name) => TokenPreset::ALL
+                .iter()
+                .find(|(preset_name, _)| *preset_name == name)
+                .map(|(_, preset)| TokenFormat::Preset(*preset))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = TokenPreset::ALL.iter().map(|(n, _)| *n).collect();
+                    format!(
+                        "unknown token format `{name}`, expected one of {} or `regex: <your regex>`",
+                        names.join(", ")
+                    )
+                }),
+            RawTokenFormat::Custom { regex } => Ok(TokenFormat::Custom { regex }),
+        }
+    }
+}
+
+impl Default for TokenFormat {
+    fn default() -> Self {
+        TokenFormat::Preset(TokenPreset::default())
+    }
+}
+
+impl TokenFormat {
+    /// The regex a token of this format has to match
+    fn regex(&self) -> &str {
+        match self {
+            TokenFormat::Preset(TokenPreset::OpenshiftSha256) => r"^sha256~[a-zA-Z0-9_-]{43}$",
+            TokenFormat::Preset(TokenPreset::OpenshiftLegacy) => r"^[a-zA-Z0-9_-]{43}$",
+            TokenFormat::Preset(TokenPreset::Jwt) => {
+                r"^[a-zA-Z0-9_-]+\.[a-zA-Z0-9_-]+\.[a-zA-Z0-9_-]*$"
+            }
+            TokenFormat::Preset(TokenPreset::Rancher) => r"^token-[a-z0-9]+:[a-z0-9]+$",
+            TokenFormat::Preset(TokenPreset::Any) => r"^\S+$",
+            TokenFormat::Custom { regex } => regex,
+        }
+    }
+
+    /// A short hint on what a token of this format looks like, for prompts
+    pub fn hint(&self) -> String {
+        match self {
+            TokenFormat::Preset(TokenPreset::OpenshiftSha256) => "sha256~xxx...".to_string(),
+            TokenFormat::Preset(TokenPreset::OpenshiftLegacy) => "43 characters".to_string(),
+            TokenFormat::Preset(TokenPreset::Jwt) => "xxxxx.yyyyy.zzzzz".to_string(),
+            TokenFormat::Preset(TokenPreset::Rancher) => "token-xxxxx:yyy...".to_string(),
+            TokenFormat::Preset(TokenPreset::Any) => "any format".to_string(),
+            TokenFormat::Custom { regex } => format!("matching `{regex}`"),
+        }
+    }
+
+    /// A description of what a token of this format looks like, for error messages
+    fn description(&self) -> String {
+        match self {
+            TokenFormat::Preset(TokenPreset::OpenshiftSha256) => {
+                "A token looks like this: `sha256~re5x9PB4OYjn7BLUubSiWkHBYg6QdyflL1-4jcIJvmQ`"
+                    .to_string()
+            }
+            TokenFormat::Preset(TokenPreset::OpenshiftLegacy) => {
+                "A token looks like this: `re5x9PB4OYjn7BLUubSiWkHBYg6QdyflL1-4jcIJvmQ`".to_string()
+            }
+            TokenFormat::Preset(TokenPreset::Jwt) => {
+                "Expected a JSON Web Token, which looks like this: `xxxxx.yyyyy.zzzzz`".to_string()
+            }
+            TokenFormat::Preset(TokenPreset::Rancher) => {
+                "Expected a Rancher token, which looks like this: `token-xxxxx:yyyyyyyy`"
+                    .to_string()
+            }
+            TokenFormat::Preset(TokenPreset::Any) => {
+                "A token can't be empty or contain whitespace".to_string()
+            }
+            TokenFormat::Custom { regex } => {
+                format!("Expected a token matching the configured format `{regex}`")
+            }
+        }
+    }
+
+    #[roxygen]
+    /// Check that a token matches this format
+    pub fn validate(
+        &self,
+        /// The token to check
+        token: &str,
+    ) -> Result<()> {
+        let token_regex = Regex::new(self.regex())
+            .with_context(|| format!("Invalid token format regex `{}`", self.regex()))?;
+
+        if !token_regex.is_match(token) {
+            bail!("Incorrect token given. {}", self.description());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1050 @@
+use roxygen::roxygen;
+use serde_yml::Value;
+
+/// A node of a block-style YAML document, with where it is in the text
+#[derive(Debug)]
+struct Node {
+    /// Where the node's text starts
+    start: usize,
+    /// Where the node's text ends, for collections after the line break of their last line
+    end: usize,
+    /// What kind of node this is
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// A block mapping, whose keys start at `column`
+    Mapping { column: usize, entries: Vec<Entry> },
+    /// A block sequence, whose dashes are at `column`
+    Sequence { column: usize, items: Vec<Item> },
+    /// A scalar on a single line
+    Scalar,
+    /// A value that can only be replaced as a whole, like a block scalar, a flow collection or an alias
+    Opaque,
+    /// A key or dash without a value
+    Empty,
+}
+
+/// A `key: value` pair of a block mapping
+#[derive(Debug)]
+struct Entry {
+    /// The unquoted key
+    key: String,
+    /// Where the entry starts, including the comments right above it
+    start: usize,
+    /// Whether the entry shares its line with a sequence's dash, so it can't be removed on its own
+    inline: bool,
+    /// Where the value starts, right after the key's colon
+    colon_end: usize,
+    /// The value
+    value: Node,
+    /// Where the entry ends, after the line break of its last line
+    end: usize,
+}
+
+/// An item of a block sequence
+#[derive(Debug)]
+struct Item {
+    /// Where the item starts, including the comments right above it
+    start: usize,
+    /// Whether the item shares its line with another dash, so it can't be removed on its own
+    inline: bool,
+    /// Where the value starts, right after the dash
+    dash_end: usize,
+    /// The value
+    value: Node,
+    /// Where the item ends, after the line break of its last line
+    end: usize,
+}
+
+/// A single line of the text
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    /// Where the line starts
+    start: usize,
+    /// Where the line ends, without its line break
+    end: usize,
+    /// Where the next line starts
+    next: usize,
+    /// The number of spaces the line starts with
+    indent: usize,
+    /// Whether the line holds anything besides whitespace and comments
+    content: bool,
+    /// Whether the line only holds a comment
+    comment: bool,
+}
+
+/// Parses the block-style YAML kubeconfigs are written in, giving up on anything fancier
+struct Parser<'a> {
+    /// The text being parsed
+    text: &'a str,
+    /// The lines of the text
+    lines: Vec<Line>,
+    /// The first line that hasn't been parsed yet
+    line: usize,
+    /// Where the last parsed line ends, after its line break
+    consumed: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Option<Self> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for raw in text.split_inclusive('\n') {
+            let line = raw.trim_end_matches(['\n', '\r']);
+            let trimmed = line.trim_start_matches(' ');
+            let comment = trimmed.starts_with('#');
+            let content = !trimmed.trim().is_empty() && !comment;
+            if content && trimmed.starts_with('\t') {
+                // tabs can't be used for indentation
+                return None;
+            }
+            lines.push(Line {
+                start,
+                end: start + line.len(),
+                next: start + raw.len(),
+                indent: line.len() - trimmed.len(),
+                content,
+                comment,
+            });
+            start += raw.len();
+        }
+
+        Some(Self {
+            text,
+            lines,
+            line: 0,
+            consumed: 0,
+        })
+    }
+
+    /// The text of a line from the given column
+    fn rest(&self, line: usize, column: usize) -> &'a str {
+        let line = self.lines[line];
+        &self.text[line.start + column..line.end]
+    }
+
+    /// The first line holding content, from the given line on
+    fn next_content(&self, from: usize) -> Option<usize> {
+        (from..self.lines.len()).find(|&l| self.lines[l].content)
+    }
+
+    /// Where the comments right above a line start, or the line itself when there are none
+    fn comments_start(&self, line: usize) -> usize {
+        let mut first = line;
+        while first > 0 && self.lines[first - 1].comment {
+            first -= 1;
+        }
+        self.lines[first].start
+    }
+
+    /// Parse a document consisting of a single block mapping
+    fn parse_document(mut self) -> Option<Node> {
+        let mut first = self.next_content(0)?;
+        while self.rest(first, self.lines[first].indent).starts_with('%') {
+            first = self.next_content(first + 1)?;
+        }
+        if self.rest(first, 0).trim_end() == "---" {
+            first = self.next_content(first + 1)?;
+        }
+
+        let root = self.parse_node(first, self.lines[first].indent, 0)?;
+        if !matches!(root.kind, Kind::Mapping { .. }) {
+            return None;
+        }
+
+        match self.next_content(self.line) {
+            Some(l) if self.rest(l, 0).trim_end() != "..." => None,
+            _ => Some(root),
+        }
+    }
+
+    /// Parse the node starting at the given line & column, belonging to the key or dash at column `parent`
+    fn parse_node(&mut self, line: usize, column: usize, parent: usize) -> Option<Node> {
+        let rest = self.rest(line, column);
+        if is_dash(rest) {
+            self.parse_sequence(line, column)
+        } else if parse_key(rest).is_some() {
+            self.parse_mapping(line, column)
+        } else {
+            self.parse_scalar(line, column, parent)
+        }
+    }
+
+    /// Parse a block sequence, whose first dash is at the given line & column
+    fn parse_sequence(&mut self, mut line: usize, column: usize) -> Option<Node> {
+        let start = self.lines[line].start + column;
+        let mut items = Vec::new();
+
+        loop {
+            let inline = self.lines[line].indent != column;
+            let item_start = if inline {
+                self.lines[line].start + column
+            } else {
+                self.comments_start(line)
+            };
+            let value = self.parse_value(line, column + 1, column, false)?;
+            items.push(Item {
+                start: item_start,
+                inline,
+                dash_end: self.lines[line].start + column + 1,
+                value,
+                end: self.consumed,
+            });
+
+            match self.next_content(self.line) {
+                Some(next)
+                    if self.lines[next].indent == column && is_dash(self.rest(next, column)) =>
+                {
+                    line = next
+                }
+                Some(next) if self.lines[next].indent > column => return None,
+                _ => break,
+            }
+        }
+
+        Some(Node {
+            start,
+            end: self.consumed,
+            kind: Kind::Sequence { column, items },
+        })
+    }
+
+    /// Parse a block mapping, whose first key is at the given line & column
+    fn parse_mapping(&mut self, mut line: usize, column: usize) -> Option<Node> {
+        let start = self.lines[line].start + column;
+        let mut entries = Vec::new();
+
+        loop {
+            let (key, colon) = parse_key(self.rest(line, column))?;
+            let inline = self.lines[line].indent != column;
+            let entry_start = if inline {
+                self.lines[line].start + column
+            } else {
+                self.comments_start(line)
+            };
+            let value = self.parse_value(line, column + colon, column, true)?;
+            entries.push(Entry {
+                key,
+                start: entry_start,
+                inline,
+                colon_end: self.lines[line].start + column + colon,
+                value,
+                end: self.consumed,
+            });
+
+            match self.next_content(self.line) {
+                Some(next) if self.lines[next].indent == column => {
+                    parse_key(self.rest(next, column))?;
+                    line = next;
+                }
+                Some(next) if self.lines[next].indent > column => return None,
+                _ => break,
+            }
+        }
+
+        Some(Node {
+            start,
+            end: self.consumed,
+            kind: Kind::Mapping { column, entries },
+        })
+    }
+
+    /// Parse the value after a key's colon or a sequence's dash
+    fn parse_value(
+        &mut self,
+        line: usize,
+        after_indicator: usize,
+        column: usize,
+        in_mapping: bool,
+    ) -> Option<Node> {
+        let rest = self.rest(line, after_indicator);
+        let mut offset = rest.len() - rest.trim_start().len();
+
+        // skip anchors & tags, they stay where they are
+        loop {
+            let value = &rest[offset..];
+            if !(value.starts_with('&') || value.starts_with('!')) {
+                break;
+            }
+            let token = value.find(' ').unwrap_or(value.len());
+            offset += token;
+            offset += rest[offset..].len() - rest[offset..].trim_start().len();
+        }
+
+        let value = &rest[offset..];
+        if !value.is_empty() && !value.starts_with('#') {
+            if in_mapping {
+                return self.parse_scalar(line, after_indicator + offset, column);
+            }
+            return self.parse_node(line, after_indicator + offset, column);
+        }
+
+        self.line = line + 1;
+        self.consumed = self.lines[line].next;
+        let empty = Node {
+            start: self.lines[line].start + after_indicator,
+            end: self.lines[line].start + after_indicator,
+            kind: Kind::Empty,
+        };
+        let Some(next) = self.next_content(self.line) else {
+            return Some(empty);
+        };
+
+        let indent = self.lines[next].indent;
+        if indent > column {
+            self.parse_node(next, indent, column)
+        } else if in_mapping && indent == column && is_dash(self.rest(next, column)) {
+            self.parse_sequence(next, column)
+        } else {
+            Some(empty)
+        }
+    }
+
+    /// Parse a scalar (or another value kman doesn't look into) starting at the given line & column,
+    /// belonging to the key or dash at column `parent`
+    fn parse_scalar(&mut self, line: usize, column: usize, parent: usize) -> Option<Node> {
+        let rest = self.rest(line, column);
+        let start = self.lines[line].start + column;
+        self.line = line + 1;
+        self.consumed = self.lines[line].next;
+
+        let (length, kind) = match rest.chars().next()? {
+            '"' => (closing_quote(rest, '"')?, Kind::Scalar),
+            '\'' => (closing_quote(rest, '\'')?, Kind::Scalar),
+            '[' | '{' => (closing_bracket(rest)?, Kind::Opaque),
+            '*' => (rest.find(' ').unwrap_or(rest.len()), Kind::Opaque),
+            '|' | '>' => {
+                // a block scalar spans every following line that is indented further than its key or dash
+                let mut end = self.lines[line].end;
+                while let Some(l) = self.lines.get(self.line) {
+                    if l.content && l.indent <= parent {
+                        break;
+                    }
+                    if l.content {
+                        end = l.end;
+                        self.consumed = l.next;
+                    }
+                    self.line += 1;
+                }
+                return Some(Node {
+                    start,
+                    end,
+                    kind: Kind::Opaque,
+                });
+            }
+            _ => (rest.find(" #").unwrap_or(rest.len()), Kind::Scalar),
+        };
+
+        // only a comment may follow the value
+        let after = rest[length..].trim_start();
+        if !after.is_empty() && !after.starts_with('#') {
+            return None;
+        }
+
+        let value = rest[..length].trim_end();
+        Some(Node {
+            start,
+            end: start + value.len(),
+            kind,
+        })
+    }
+}
+
+/// Whether a line (from its indentation on) starts a sequence item
+fn is_dash(rest: &str) -> bool {
+    rest == "-" || rest.starts_with("- ")
+}
+
+/// Parse the key of a `key: value` line, returning it unquoted together with the position after its colon
+fn parse_key(rest: &str) -> Option<(String, usize)> {
+    let (key, after_key) = match rest.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = closing_quote(rest, quote)?;
+            let key: Value = serde_yml::from_str(&rest[..end]).ok()?;
+            (key.as_str()?.to_string(), end)
+        }
+        '[' | '{' | '&' | '*' | '!' | '|' | '>' | '#' | '%' | '@' | '`' | '?' => return None,
+        _ => {
+            let end = rest
+                .match_indices(':')
+                .map(|(i, _)| i)
+                .find(|&i| rest[i + 1..].is_empty() || rest[i + 1..].starts_with(' '))?;
+            if rest[..end].contains(" #") {
+                return None;
+            }
+            (rest[..end].trim_end().to_string(), end)
+        }
+    };
+
+    let after = &rest[after_key..];
+    if !after.starts_with(':') || !(after.len() == 1 || after[1..].starts_with(' ')) {
+        return None;
+    }
+
+    Some((key, after_key + 1))
+}
+
+/// The length of a quoted string, including its quotes
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            '\'' if quote == '\'' && chars.peek().is_some_and(|&(_, c)| c == '\'') => {
+                chars.next();
+            }
+            c if c == quote => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The length of a flow collection on a single line, including its brackets
+fn closing_bracket(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            '"' | '\'' => {
+                i += closing_quote(&text[i..], c)?;
+                continue;
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+
+    None
+}
+
+/// A replacement of part of the text
+struct Edit {
+    /// Where the replaced part starts
+    start: usize,
+    /// Where the replaced part ends
+    end: usize,
+    /// The replacement
+    text: String,
+}
+
+#[roxygen]
+/// Apply the changes between two versions of a document to its text, leaving everything else
+/// (comments, key & entry order, quoting, anchors) as it is.
+/// Returns `None` when the changes can't be made in place, in which case the document has to be serialized as a whole.
+/// Only the parts that differ between `old` and `new` have to match the text, so `old` may be a normalized form of it.
+/// Callers have to check that the result reads back as intended, editing an anchored value changes its aliases as well
+pub fn patch(
+    /// The document's text
+    text: &str,
+    /// The document the text holds, or a normalized form of it
+    old: &Value,
+    /// The document the text should hold
+    new: &Value,
+) -> Option<String> {
+    // entries are appended after the last line, which needs a line break of its own
+    let mut patched = text.to_string();
+    if !patched.is_empty() && !patched.ends_with('\n') {
+        patched.push('\n');
+    }
+    let root = Parser::new(&patched)?.parse_document()?;
+
+    let mut edits = Vec::new();
+    diff(&patched, &root, old, new, &mut edits)?;
+    // insertions go before whatever starts at the same place, edits at the same place keep their order
+    edits.sort_by_key(|e| (e.start, e.end));
+    if edits.windows(2).any(|pair| pair[0].end > pair[1].start) {
+        return None;
+    }
+
+    for edit in edits.iter().rev() {
+        patched.replace_range(edit.start..edit.end, &edit.text);
+    }
+
+    Some(patched)
+}
+
+/// Collect the edits that turn the text of a node holding `old` into text holding `new`
+fn diff(text: &str, node: &Node, old: &Value, new: &Value, edits: &mut Vec<Edit>) -> Option<()> {
+    if old == new {
+        return Some(());
+    }
+
+    match (&node.kind, old, new) {
+        (Kind::Mapping { column, entries }, Value::Mapping(old), Value::Mapping(new))
+            if !new.is_empty() =>
+        {
+            for key in old.keys().filter(|key| !new.contains_key(*key)) {
+                let key = key.as_str()?;
+                if let Some(entry) = entries.iter().find(|e| e.key == key) {
+                    if entry.inline {
+                        return None;
+                    }
+                    edits.push(Edit {
+                        start: entry.start,
+                        end: entry.end,
+                        text: String::new(),
+                    });
+                }
+            }
+
+            let mut added = serde_yml::Mapping::new();
+            for (key, new_value) in new {
+                let old_value = old.get(key);
+                if old_value == Some(new_value) {
+                    continue;
+                }
+                let name = key.as_str()?;
+                match entries.iter().find(|e| e.key == name) {
+                    Some(entry) => diff_entry(
+                        text,
+                        entry,
+                        *column,
+                        old_value.unwrap_or(&Value::Null),
+                        new_value,
+                        edits,
+                    )?,
+                    None => {
+                        added.insert(key.clone(), new_value.clone());
+                    }
+                }
+            }
+            if !added.is_empty() {
+                edits.push(Edit {
+                    start: node.end,
+                    end: node.end,
+                    text: indent(&serde_yml::to_string(&added).ok()?, *column),
+                });
+            }
+            Some(())
+        }
+        (Kind::Sequence { column, items }, Value::Sequence(old), Value::Sequence(new))
+            if !new.is_empty() && items.len() == old.len() =>
+        {
+            let mut added = Vec::new();
+            for (old_index, new_index) in match_items(old, new) {
+                match (old_index, new_index) {
+                    (Some(o), Some(n)) => {
+                        diff_item(text, &items[o], *column, &old[o], &new[n], edits)?
+                    }
+                    (Some(o), None) => {
+                        if items[o].inline {
+                            return None;
+                        }
+                        edits.push(Edit {
+                            start: items[o].start,
+                            end: items[o].end,
+                            text: String::new(),
+                        });
+                    }
+                    (None, Some(n)) => added.push(new[n].clone()),
+                    (None, None) => {}
+                }
+            }
+            if !added.is_empty() {
+                edits.push(Edit {
+                    start: node.end,
+                    end: node.end,
+                    text: indent(&serde_yml::to_string(&added).ok()?, *column),
+                });
+            }
+            Some(())
+        }
+        (Kind::Scalar, _, _) => {
+            edits.push(Edit {
+                start: node.start,
+                end: node.end,
+                text: render_scalar(new, &text[node.start..node.end])?,
+            });
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+/// Collect the edits for the value of a mapping entry, replacing the value as a whole when it can't be edited
+fn diff_entry(
+    text: &str,
+    entry: &Entry,
+    column: usize,
+    old: &Value,
+    new: &Value,
+    edits: &mut Vec<Edit>,
+) -> Option<()> {
+    let mut nested = Vec::new();
+    if diff(text, &entry.value, old, new, &mut nested).is_some() {
+        edits.append(&mut nested);
+        return Some(());
+    }
+
+    let replacement = match (new, &entry.value.kind) {
+        (Value::Mapping(m), kind) if !m.is_empty() => {
+            let column = match kind {
+                Kind::Mapping { column, .. } => *column,
+                _ => column + 2,
+            };
+            format!("\n{}", indent(&serde_yml::to_string(new).ok()?, column))
+        }
+        (Value::Sequence(s), kind) if !s.is_empty() => {
+            let column = match kind {
+                Kind::Sequence { column, .. } => *column,
+                _ => column,
+            };
+            format!("\n{}", indent(&serde_yml::to_string(new).ok()?, column))
+        }
+        _ => format!(" {}\n", render_inline(new)?),
+    };
+    edits.push(Edit {
+        start: entry.colon_end,
+        end: entry.end,
+        text: replacement,
+    });
+    Some(())
+}
+
+/// Collect the edits for the value of a sequence item, replacing the value as a whole when it can't be edited
+fn diff_item(
+    text: &str,
+    item: &Item,
+    column: usize,
+    old: &Value,
+    new: &Value,
+    edits: &mut Vec<Edit>,
+) -> Option<()> {
+    let mut nested = Vec::new();
+    if diff(text, &item.value, old, new, &mut nested).is_some() {
+        edits.append(&mut nested);
+        return Some(());
+    }
+
+    let replacement = match new {
+        Value::Mapping(m) if !m.is_empty() => serde_yml::to_string(new).ok()?,
+        Value::Sequence(s) if !s.is_empty() => serde_yml::to_string(new).ok()?,
+        _ => format!("{}\n", render_inline(new)?),
+    };
+    // the first line goes right after the dash, the rest is indented past it
+    let (first, rest) = replacement.split_once('\n')?;
+    edits.push(Edit {
+        start: item.dash_end,
+        end: item.end,
+        text: format!(" {first}\n{}", indent(rest, column + 2)),
+    });
+    Some(())
+}
+
+/// Pair the items of two versions of a sequence, by their `name` when every item has one and by position otherwise.
+/// Items that were only renamed are paired as well, so they keep their place
+fn match_items(old: &[Value], new: &[Value]) -> Vec<(Option<usize>, Option<usize>)> {
+    let names = |items: &[Value]| -> Option<Vec<Value>> {
+        items
+            .iter()
+            .map(|item| item.get("name").filter(|name| name.is_string()).cloned())
+            .collect()
+    };
+    let (Some(old_names), Some(new_names)) = (names(old), names(new)) else {
+        return (0..old.len().max(new.len()))
+            .map(|i| ((i < old.len()).then_some(i), (i < new.len()).then_some(i)))
+            .collect();
+    };
+
+    let mut pairs = Vec::new();
+    let mut renamed = vec![false; new.len()];
+    for (o, name) in old_names.iter().enumerate() {
+        let n = new_names.iter().position(|n| n == name).or_else(|| {
+            // an item that only differs in its name was renamed
+            (0..new.len()).find(|&n| {
+                !renamed[n]
+                    && !old_names.contains(&new_names[n])
+                    && without_name(&old[o]) == without_name(&new[n])
+            })
+        });
+        if let Some(n) = n {
+            renamed[n] = true;
+        }
+        pairs.push((Some(o), n));
+    }
+    for (n, name) in new_names.iter().enumerate() {
+        if !renamed[n] && !old_names.contains(name) {
+            pairs.push((None, Some(n)));
+        }
+    }
+
+    pairs
+}
+
+/// A sequence item without its `name`
+fn without_name(item: &Value) -> Value {
+    let mut item = item.clone();
+    if let Value::Mapping(mapping) = &mut item {
+        mapping.remove("name");
+    }
+    item
+}
+
+/// Indent every line of a block of text to the given column
+fn indent(text: &str, column: usize) -> String {
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{line}", " ".repeat(column))
+            }
+        })
+        .collect()
+}
+
+/// Write a value on a single line, for scalars and empty collections
+fn render_inline(value: &Value) -> Option<String> {
+    match value {
+        Value::Mapping(m) if m.is_empty() => Some("{}".to_string()),
+        Value::Sequence(s) if s.is_empty() => Some("[]".to_string()),
+        _ => render_scalar(value, ""),
+    }
+}
+
+/// Write a scalar on a single line, quoted the same way as the scalar it replaces
+fn render_scalar(value: &Value, replaced: &str) -> Option<String> {
+    if let Value::String(string) = value {
+        if !string.contains('\n') {
+            if replaced.starts_with('"') {
+                return serde_json::to_string(string).ok();
+            }
+            if replaced.starts_with('\'') {
+                return Some(format!("'{}'", string.replace('\'', "''")));
+            }
+        }
+    }
+
+    if !matches!(
+        value,
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_)
+    ) {
+        return None;
+    }
+
+    let rendered = serde_yml::to_string(value).ok()?;
+    let rendered = rendered.trim_end_matches('\n');
+    if rendered.contains('\n') {
+        return None;
+    }
+
+    Some(rendered.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KUBECONFIG: &str = r#"# my kubeconfig
+apiVersion: v1
+clusters:
+# prod, ask before touching
+- cluster:
+    server: "https://api.prod.example.com:6443" # behind the VPN
+  name: prod
+- cluster:
+    server: https://api.dev.example.com:6443
+  name: dev
+contexts:
+- context:
+    cluster: prod
+    user: prod
+  name: prod
+- context:
+    cluster: dev
+    user: dev
+  name: dev
+current-context: prod # default
+
+kind: Config
+users:
+- name: prod
+  user:
+    token: 'sha256~aaaa'
+# the dev token is refreshed daily
+- name: dev
+  user:
+    token: sha256~bbbb # dev
+"#;
+
+    /// Patch a document after making a change to the value it holds
+    fn edit(text: &str, change: impl FnOnce(&mut Value)) -> Option<String> {
+        let old: Value = serde_yml::from_str(text).unwrap();
+        let mut new = old.clone();
+        change(&mut new);
+        patch(text, &old, &new)
+    }
+
+    /// The item of a sequence at `key` with the given name
+    fn named<'a>(value: &'a mut Value, key: &str, name: &str) -> &'a mut Value {
+        value[key]
+            .as_sequence_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|item| item["name"] == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_comments_around_edited_keys() {
+        let patched = edit(KUBECONFIG, |k| {
+            k["current-context"] = "dev".into();
+            named(k, "users", "dev")["user"]["token"] = "sha256~cccc".into();
+        })
+        .unwrap();
+
+        assert_eq!(
+            patched,
+            KUBECONFIG
+                .replace(
+                    "current-context: prod # default",
+                    "current-context: dev # default"
+                )
+                .replace("token: sha256~bbbb # dev", "token: sha256~cccc # dev")
+        );
+    }
+
+    #[test]
+    fn keeps_quotes_of_edited_scalars() {
+        let patched = edit(KUBECONFIG, |k| {
+            named(k, "users", "prod")["user"]["token"] = "sha256~it's".into();
+            named(k, "clusters", "prod")["cluster"]["server"] = "https://\"new\"".into();
+        })
+        .unwrap();
+
+        assert!(patched.contains("    token: 'sha256~it''s'\n"));
+        assert!(patched.contains("    server: \"https://\\\"new\\\"\" # behind the VPN\n"));
+    }
+
+    #[test]
+    fn renders_scalars() {
+        assert_eq!(render_scalar(&"plain".into(), "old").unwrap(), "plain");
+        // values that would read back as something else get quoted
+        assert_eq!(render_scalar(&"true".into(), "old").unwrap(), "'true'");
+        assert_eq!(render_scalar(&"a: b".into(), "old").unwrap(), "'a: b'");
+        assert_eq!(
+            render_scalar(&"# no comment".into(), "old").unwrap(),
+            "'# no comment'"
+        );
+        assert_eq!(render_scalar(&Value::Bool(true), "old").unwrap(), "true");
+        assert_eq!(render_scalar(&Value::from(42), "old").unwrap(), "42");
+        // the quote style of the replaced scalar is kept
+        assert_eq!(
+            render_scalar(&"tab\there".into(), "\"old\"").unwrap(),
+            "\"tab\\there\""
+        );
+        assert_eq!(render_scalar(&"it's".into(), "'old'").unwrap(), "'it''s'");
+        // multi-line strings and collections don't fit on one line
+        assert_eq!(render_scalar(&"two\nlines".into(), "'old'"), None);
+        assert_eq!(render_scalar(&Value::Sequence(Vec::new()), "old"), None);
+    }
+
+    #[test]
+    fn removes_entries_together_with_their_comments() {
+        let patched = edit(KUBECONFIG, |k| {
+            k["users"].as_sequence_mut().unwrap().remove(1);
+            k["clusters"].as_sequence_mut().unwrap().remove(0);
+        })
+        .unwrap();
+
+        assert_eq!(
+            patched,
+            KUBECONFIG
+                .replace(
+                    "# the dev token is refreshed daily\n- name: dev\n  user:\n    token: sha256~bbbb # dev\n",
+                    ""
+                )
+                .replace(
+                    "# prod, ask before touching\n- cluster:\n    server: \"https://api.prod.example.com:6443\" # behind the VPN\n  name: prod\n",
+                    ""
+                )
+        );
+    }
+
+    #[test]
+    fn adds_entries_after_the_existing_ones() {
+        let patched = edit(KUBECONFIG, |k| {
+            let user: Value =
+                serde_yml::from_str("name: stage\nuser:\n  token: sha256~dddd").unwrap();
+            k["users"].as_sequence_mut().unwrap().push(user);
+            k["preferences"] = Value::Mapping(Default::default());
+        })
+        .unwrap();
+
+        assert_eq!(
+            patched,
+            format!(
+                "{KUBECONFIG}- name: stage\n  user:\n    token: sha256~dddd\npreferences: {{}}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn adds_keys_to_nested_mappings() {
+        let patched = edit(KUBECONFIG, |k| {
+            named(k, "contexts", "dev")["context"]["namespace"] = "web".into();
+        })
+        .unwrap();
+
+        assert_eq!(
+            patched,
+            KUBECONFIG.replace("    user: dev\n", "    user: dev\n    namespace: web\n")
+        );
+    }
+
+    #[test]
+    fn renamed_entries_keep_their_place_and_comments() {
+        let patched = edit(KUBECONFIG, |k| {
+            named(k, "clusters", "prod")["name"] = "production".into();
+            named(k, "contexts", "prod")["context"]["cluster"] = "production".into();
+        })
+        .unwrap();
+
+        assert_eq!(
+            patched,
+            KUBECONFIG
+                .replace("  name: prod\n- cluster:", "  name: production\n- cluster:")
+                .replace("    cluster: prod\n", "    cluster: production\n")
+        );
+    }
+
+    #[test]
+    fn matches_items_by_name() {
+        let item = |yaml: &str| -> Value { serde_yml::from_str(yaml).unwrap() };
+        let old = [
+            item("{name: a, x: 1}"),
+            item("{name: b, x: 2}"),
+            item("{name: c, x: 3}"),
+        ];
+
+        // removed, added & reordered items
+        let new = [
+            item("{name: c, x: 3}"),
+            item("{name: d, x: 4}"),
+            item("{name: a, x: 5}"),
+        ];
+        assert_eq!(
+            match_items(&old, &new),
+            [
+                (Some(0), Some(2)),
+                (Some(1), None),
+                (Some(2), Some(0)),
+                (None, Some(1))
+            ]
+        );
+
+        // a renamed item is paired with its new name
+        let new = [
+            item("{name: a, x: 1}"),
+            item("{name: z, x: 2}"),
+            item("{name: c, x: 3}"),
+        ];
+        assert_eq!(
+            match_items(&old, &new),
+            [(Some(0), Some(0)), (Some(1), Some(1)), (Some(2), Some(2))]
+        );
+
+        // items without names are paired by position
+        let old = [item("1"), item("2")];
+        let new = [item("3")];
+        assert_eq!(
+            match_items(&old, &new),
+            [(Some(0), Some(0)), (Some(1), None)]
+        );
+    }
+
+    #[test]
+    fn keeps_anchors_and_aliases() {
+        let text = "contexts:\n- context:\n    cluster: dev\n    namespace: &ns web\n  name: a\n- context:\n    cluster: dev\n    namespace: *ns\n  name: b\ncurrent-context: a\n";
+        let patched = edit(text, |k| k["current-context"] = "b".into()).unwrap();
+        assert_eq!(
+            patched,
+            text.replace("current-context: a", "current-context: b")
+        );
+
+        // changing an alias replaces it with the value
+        let patched = edit(text, |k| {
+            named(k, "contexts", "b")["context"]["namespace"] = "api".into()
+        })
+        .unwrap();
+        assert_eq!(patched, text.replace("namespace: *ns", "namespace: api"));
+
+        // changing the anchored value changes the alias as well, which callers have to catch
+        let mut new: Value = serde_yml::from_str(text).unwrap();
+        named(&mut new, "contexts", "a")["context"]["namespace"] = "api".into();
+        let patched = edit(text, |k| {
+            named(k, "contexts", "a")["context"]["namespace"] = "api".into()
+        })
+        .unwrap();
+        assert_ne!(serde_yml::from_str::<Value>(&patched).unwrap(), new);
+    }
+
+    #[test]
+    fn replaces_flow_collections_as_a_whole() {
+        let text = "contexts:\n- context: {cluster: dev, user: dev} # compact\n  name: dev\nusers:\n- name: dev\n  user:\n    exec:\n      args: [get-token, --cluster, dev]\n      command: aws\n";
+
+        // untouched flow collections stay as they are
+        let patched = edit(text, |k| {
+            named(k, "users", "dev")["user"]["exec"]["command"] = "gcloud".into()
+        })
+        .unwrap();
+        assert_eq!(patched, text.replace("command: aws", "command: gcloud"));
+
+        let patched = edit(text, |k| {
+            named(k, "contexts", "dev")["context"]["cluster"] = "prod".into();
+            named(k, "users", "dev")["user"]["exec"]["args"][2] = "prod".into();
+        })
+        .unwrap();
+        assert_eq!(
+            patched,
+            "contexts:\n- context:\n    cluster: prod\n    user: dev\n  name: dev\nusers:\n- name: dev\n  user:\n    exec:\n      args:\n      - get-token\n      - '--cluster'\n      - prod\n      command: aws\n"
+        );
+    }
+
+    #[test]
+    fn handles_block_scalars() {
+        let text = "users:\n- name: dev\n  user:\n    client-key-data: |\n      line one\n      line two\n    token: old\ncurrent-context: dev\n";
+
+        let patched = edit(text, |k| {
+            named(k, "users", "dev")["user"]["token"] = "new".into()
+        })
+        .unwrap();
+        assert_eq!(patched, text.replace("token: old", "token: new"));
+
+        let patched = edit(text, |k| {
+            named(k, "users", "dev")["user"]["client-key-data"] = "replaced".into()
+        })
+        .unwrap();
+        assert_eq!(
+            patched,
+            text.replace(
+                "client-key-data: |\n      line one\n      line two\n",
+                "client-key-data: replaced\n"
+            )
+        );
+    }
+
+    #[test]
+    fn gives_up_on_documents_it_cannot_parse() {
+        let old: Value = serde_yml::from_str("a: 1").unwrap();
+        let new: Value = serde_yml::from_str("a: 2").unwrap();
+
+        assert_eq!(patch("{a: 1}", &old, &new), None);
+        assert_eq!(patch("a:\n\t1", &old, &new), None);
+        assert_eq!(patch("a: 1\n---\na: 1\n", &old, &new), None);
+        assert_eq!(patch("a: 1", &old, &new).unwrap(), "a: 2\n");
+    }
+}